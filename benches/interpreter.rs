@@ -0,0 +1,85 @@
+//! Day 5's diagnostic samples, and a loop that runs the same few
+//! instructions many times, under the interpreter with and without its cache
+//! of decoded instructions, and compiled to closures.
+//!
+//! Run with `cargo bench`.
+
+use advent_of_code_2019::intcode::compiler::CompiledProgram;
+use advent_of_code_2019::intcode::fuzz::DAY_5_SAMPLES;
+use advent_of_code_2019::intcode::Machine;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// An input that takes every sample down its longest path.
+const INPUT: i64 = 8;
+
+/// Counts down from 10,000 to zero, for 20,002 instructions.
+const COUNTDOWN: [i64; 12] = [1101, 0, 10_000, 20, 1001, 20, -1, 20, 1005, 20, 4, 99];
+
+fn load(program: &[i64]) -> Machine {
+    let mut machine = Machine::new(program);
+    machine.push_input(INPUT);
+    machine
+}
+
+fn day_5_samples(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day 5 samples");
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            for program in &DAY_5_SAMPLES {
+                let mut machine = load(program);
+                machine.set_decode_cache(false);
+                machine.run().unwrap();
+            }
+        })
+    });
+
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            for program in &DAY_5_SAMPLES {
+                load(program).run().unwrap();
+            }
+        })
+    });
+
+    let compiled: Vec<_> = DAY_5_SAMPLES
+        .iter()
+        .map(|program| CompiledProgram::compile(program, &[]))
+        .collect();
+
+    group.bench_function("compiled", |b| {
+        b.iter(|| {
+            for (program, compiled) in DAY_5_SAMPLES.iter().zip(&compiled) {
+                compiled.run(&mut load(program)).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn countdown(c: &mut Criterion) {
+    let mut group = c.benchmark_group("countdown");
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            let mut machine = Machine::new(&COUNTDOWN);
+            machine.set_decode_cache(false);
+            machine.run().unwrap();
+        })
+    });
+
+    group.bench_function("cached", |b| {
+        b.iter(|| Machine::new(&COUNTDOWN).run().unwrap())
+    });
+
+    let compiled = CompiledProgram::compile(&COUNTDOWN, &[]);
+    group.bench_function("compiled", |b| {
+        b.iter(|| compiled.run(&mut Machine::new(&COUNTDOWN)).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, day_5_samples, countdown);
+criterion_main!(benches);
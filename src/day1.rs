@@ -36,7 +36,7 @@ impl crate::PuzzleSolver for DayOne {
                 let mut accumulator: usize = 0;
                 while requires_fuel_addition(last_fuel) {
                     let new_fuel = get_fuel_cost(last_fuel);
-                    accumulator = accumulator + new_fuel;
+                    accumulator += new_fuel;
                     last_fuel = new_fuel;
                 }
 
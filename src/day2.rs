@@ -1,52 +1,30 @@
+use crate::intcode::search::Search;
+use crate::intcode::symbolic::SymbolicMachine;
+use crate::intcode::{self, Machine, VmError};
+
+/// Day 2 programs are a few dozen instructions long; anything running much
+/// longer than this isn't one.
+const STEP_LIMIT: u64 = 10_000;
+
 fn run_interpreter(
-    source_program: &Vec<usize>,
-    noun: Option<usize>,
-    verb: Option<usize>,
-) -> Vec<usize> {
-    let mut opcodes = source_program.clone();
+    source_program: &[i64],
+    noun: Option<i64>,
+    verb: Option<i64>,
+) -> Result<Vec<i64>, VmError> {
+    let mut machine = Machine::new(source_program);
+    machine.set_step_limit(Some(STEP_LIMIT));
+    machine.set_cycle_detection(true);
 
     if let Some(noun) = noun {
-        opcodes[1] = noun;
+        machine.write(1, noun);
     }
 
     if let Some(verb) = verb {
-        opcodes[2] = verb;
+        machine.write(2, verb);
     }
 
-    let mut program_counter = 0;
-
-    loop {
-        if program_counter >= opcodes.len() {
-            break;
-        }
-
-        let opcode = *opcodes.get(program_counter).expect("out of bounds PC");
-
-        if opcode == 99 {
-            break;
-        }
-
-        let lhs_location = *opcodes.get(program_counter + 1).expect("invalid program");
-        let rhs_location = *opcodes.get(program_counter + 2).expect("invalid program");
-        let target_location = *opcodes.get(program_counter + 3).expect("invalid program");
-
-        let lhs = opcodes.get(lhs_location).expect("out of bounds LHS");
-        let rhs = opcodes.get(rhs_location).expect("out of bounds RHS");
-
-        match opcode {
-            1 => {
-                opcodes[target_location] = lhs + rhs;
-            }
-            2 => {
-                opcodes[target_location] = lhs * rhs;
-            }
-            _ => panic!("unknown opcode {}", opcode),
-        }
-
-        program_counter += 4;
-    }
-
-    opcodes
+    machine.run()?;
+    Ok(machine.memory().to_vec())
 }
 
 pub struct DayTwo;
@@ -57,30 +35,50 @@ impl crate::PuzzleSolver for DayTwo {
     }
 
     fn solve(&self, input: &str) {
-        let source_program: Vec<usize> = input
-            .split(",")
-            .map(|o| o.parse::<usize>().expect("could not parse to number"))
-            .collect();
-
-        let opcodes = run_interpreter(&source_program, Some(12), Some(2));
-        println!("Part 1: Value at position 0: {}", opcodes.get(0).unwrap());
-
-        const TARGET_VALUE: usize = 19_690_720;
-
-        'outer: for noun in 0..=99 {
-            for verb in 0..=99 {
-                let output_memory = run_interpreter(&source_program, Some(noun), Some(verb));
-                if output_memory[0] == TARGET_VALUE {
-                    println!(
-                        "Part 2:\n\tNoun: {}\n\tVerb: {}\n\t100 * noun + verb: {}",
-                        noun,
-                        verb,
-                        100 * noun + verb
-                    );
-
-                    break 'outer;
-                }
+        let source_program = intcode::parse_program(input);
+
+        match run_interpreter(&source_program, Some(12), Some(2)) {
+            Ok(opcodes) => println!("Part 1: Value at position 0: {}", opcodes[0]),
+            Err(error) => println!("Part 1: Program failed: {}", error),
+        }
+
+        const TARGET_VALUE: i64 = 19_690_720;
+
+        // The program is straight-line arithmetic, so position 0 can usually
+        // be worked out as a polynomial in the noun and verb and solved
+        // directly. If not, fall back to trying every pair.
+        let mut symbolic = SymbolicMachine::new(&source_program, &[1, 2]);
+        let pair = match symbolic.run(STEP_LIMIT).and_then(|_| symbolic.value(0)) {
+            Ok(polynomial) => {
+                println!(
+                    "Part 2: Position 0 is {}",
+                    polynomial.format(&["noun", "verb"])
+                );
+                polynomial
+                    .solve(TARGET_VALUE, &[0..=99, 0..=99])
+                    .into_iter()
+                    .next()
+            }
+            Err(error) => {
+                println!("Part 2: Searching, since {}", error);
+                let mut search = Search::new(&source_program, &[1, 2], 0..=99)
+                    .expect("noun and verb searches are small");
+                search.set_step_limit(Some(STEP_LIMIT));
+                search.first(|machine| machine.read(0) == TARGET_VALUE)
             }
+        };
+
+        match pair {
+            Some(pair) => {
+                let (noun, verb) = (pair[0], pair[1]);
+                println!(
+                    "Part 2:\n\tNoun: {}\n\tVerb: {}\n\t100 * noun + verb: {}",
+                    noun,
+                    verb,
+                    100 * noun + verb
+                );
+            }
+            None => println!("Part 2: No noun and verb produce {}", TARGET_VALUE),
         }
     }
 }
@@ -92,20 +90,58 @@ mod test {
     #[test]
     fn interpreter() {
         assert_eq!(
-            run_interpreter(&vec![1, 0, 0, 0, 99], None, None),
+            run_interpreter(&[1, 0, 0, 0, 99], None, None).unwrap(),
             vec![2, 0, 0, 0, 99]
         );
         assert_eq!(
-            run_interpreter(&vec![2, 3, 0, 3, 99], None, None),
+            run_interpreter(&[2, 3, 0, 3, 99], None, None).unwrap(),
             vec![2, 3, 0, 6, 99]
         );
         assert_eq!(
-            run_interpreter(&vec![2, 4, 4, 5, 99, 0], None, None),
+            run_interpreter(&[2, 4, 4, 5, 99, 0], None, None).unwrap(),
             vec![2, 4, 4, 5, 99, 9801]
         );
         assert_eq!(
-            run_interpreter(&vec![1, 1, 1, 4, 99, 5, 6, 0, 99], None, None),
+            run_interpreter(&[1, 1, 1, 4, 99, 5, 6, 0, 99], None, None).unwrap(),
             vec![30, 1, 1, 4, 2, 5, 6, 0, 99]
         )
     }
+
+    #[test]
+    fn search() {
+        let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let pairs = Search::new(&program, &[1, 2], 0..=11)
+            .unwrap()
+            .all(|m| m.read(0) == 3500);
+
+        for pair in &pairs {
+            let memory = run_interpreter(&program, Some(pair[0]), Some(pair[1])).unwrap();
+            assert_eq!(memory[0], 3500);
+        }
+
+        assert_eq!(pairs.len(), 2);
+    }
+
+    #[test]
+    fn symbolic() {
+        let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let mut machine = SymbolicMachine::new(&program, &[1, 2]);
+        machine.run(STEP_LIMIT).unwrap();
+
+        // The sample reads through its noun and verb, so its result isn't a
+        // polynomial of them
+        assert!(machine.value(0).is_err());
+
+        let program = [1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 100];
+        let mut machine = SymbolicMachine::new(&program, &[1, 2]);
+        machine.run(STEP_LIMIT).unwrap();
+
+        let polynomial = machine.value(0).unwrap();
+        assert_eq!(polynomial.format(&["noun", "verb"]), "100*noun + 100*verb");
+
+        for pair in polynomial.solve(1200, &[0..=99, 0..=99]) {
+            let memory = run_interpreter(&program, Some(pair[0]), Some(pair[1])).unwrap();
+            assert_eq!(memory[0], 1200);
+        }
+    }
 }
@@ -39,7 +39,7 @@ fn distance_along_wire((target_x, target_y): (isize, isize), wire_path: &str) ->
     let mut distance = 0;
 
     for segment_str in wire_path.split(",") {
-        let segment = PathSegment::parse(&segment_str);
+        let segment = PathSegment::parse(segment_str);
         let (x_delta, y_delta) = direction_to_xy_deltas(&segment.direction);
 
         for _count in 0..segment.magnitude {
@@ -70,7 +70,7 @@ struct Grid<'a> {
 }
 
 impl<'a> Grid<'a> {
-    fn new(wire_paths: &'a Vec<&'a str>) -> Grid {
+    fn new(wire_paths: &'a Vec<&'a str>) -> Grid<'a> {
         let mut cells = HashMap::new();
         cells.insert((0, 0), Cell::Origin);
         let mut grid = Grid { cells, wire_paths };
@@ -80,7 +80,7 @@ impl<'a> Grid<'a> {
             let mut y = 0;
 
             for segment_str in wire_path.split(",") {
-                let segment = PathSegment::parse(&segment_str);
+                let segment = PathSegment::parse(segment_str);
                 let (x_delta, y_delta) = direction_to_xy_deltas(&segment.direction);
 
                 for _count in 0..segment.magnitude {
@@ -168,7 +168,7 @@ impl crate::PuzzleSolver for DayThree {
 
     fn solve(&self, input: &str) {
         let lines = &input.lines().collect();
-        let grid = Grid::new(&lines);
+        let grid = Grid::new(lines);
         let (target_x, target_y) = grid.get_closest_overlap_point();
         let closest_distance = distance_from_origin((target_x, target_y));
         println!(
@@ -5,7 +5,7 @@ fn get_digits(number: usize) -> Vec<u8> {
     while remainder > 0 {
         let current_digit = (remainder % 10) as u8;
         digits.push(current_digit);
-        remainder = remainder / 10;
+        remainder /= 10;
     }
 
     digits.reverse();
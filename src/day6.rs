@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
-const ORIGIN_IDENTIFIER: &'static str = "YOU";
-const GOAL_IDENTIFIER: &'static str = "SAN";
+const ORIGIN_IDENTIFIER: &str = "YOU";
+const GOAL_IDENTIFIER: &str = "SAN";
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 struct Orbit<'a> {
@@ -10,7 +10,7 @@ struct Orbit<'a> {
     orbits: HashSet<&'a str>,
 }
 
-fn parse_map(map: &str) -> HashMap<&str, Orbit> {
+fn parse_map(map: &str) -> HashMap<&str, Orbit<'_>> {
     let mut orbits = HashMap::new();
 
     let orbit_pairs: Vec<(&str, &str)> = map
@@ -88,7 +88,7 @@ fn is_descended_from(orbits: &HashMap<&str, Orbit>, parent: &str, descendant: &s
 
     if parent_orbit.orbits.contains(descendant) {
         return true;
-    } else if parent_orbit.orbits.len() == 0 {
+    } else if parent_orbit.orbits.is_empty() {
         return false;
     } else {
         for child in &parent_orbit.orbits {
@@ -283,7 +283,7 @@ mod tests {
             },
         );
 
-        let parsed = parse_map(&input);
+        let parsed = parse_map(input);
 
         assert_eq!(
             parsed, expected,
@@ -306,7 +306,7 @@ mod tests {
             J)K
             K)L";
 
-        let orbits = parse_map(&input);
+        let orbits = parse_map(input);
         let direct_orbits = orbits.len() - 1;
 
         let mut indirect_orbit_count = 0usize;
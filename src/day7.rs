@@ -0,0 +1,29 @@
+use crate::intcode::{self, amplifiers};
+
+pub struct DaySeven;
+
+impl crate::PuzzleSolver for DaySeven {
+    fn description(&self) -> &'static str {
+        "Day 7: Amplification Circuit"
+    }
+
+    fn solve(&self, input: &str) {
+        let source_program = intcode::parse_program(input);
+
+        match amplifiers::max_thruster_signal(&source_program, &[0, 1, 2, 3, 4], false) {
+            Ok((signal, phases)) => println!(
+                "Part 1: Highest thruster signal: {} (phases {:?})",
+                signal, phases
+            ),
+            Err(error) => println!("Part 1: Amplifiers failed: {}", error),
+        }
+
+        match amplifiers::max_thruster_signal(&source_program, &[5, 6, 7, 8, 9], true) {
+            Ok((signal, phases)) => println!(
+                "Part 2: Highest thruster signal with feedback: {} (phases {:?})",
+                signal, phases
+            ),
+            Err(error) => println!("Part 2: Amplifiers failed: {}", error),
+        }
+    }
+}
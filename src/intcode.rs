@@ -0,0 +1,1309 @@
+//! The Intcode virtual machine shared by every Intcode puzzle.
+//!
+//! A `Machine` owns a copy of a program's memory, a queue of pending input
+//! values and the output it has produced so far. Puzzles build one from a
+//! parsed program, patch memory or push input as needed, and `run` it until
+//! it halts. Memory grows on demand, so programs may read or write any
+//! non-negative address below the machine's memory limit. Programs that talk
+//! to something else can instead be driven with `resume`, which pauses
+//! whenever the machine produces output or wants input that hasn't been
+//! provided yet.
+//!
+//! Malformed programs never panic the machine; every way an instruction can
+//! fail is reported as a `VmError` naming the offending instruction.
+
+use cycles::CycleDetector;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+pub mod amplifiers;
+pub mod ascii;
+pub mod assembler;
+pub mod bigint;
+pub mod cfg;
+pub mod compiler;
+pub mod cycles;
+pub mod debugger;
+pub mod devices;
+pub mod disassembler;
+pub mod fuzz;
+pub mod history;
+pub mod network;
+pub mod profiler;
+pub mod search;
+pub mod self_modification;
+pub mod snapshot;
+pub mod symbolic;
+pub mod threaded;
+pub mod trace;
+
+/// The default cap on addressable memory, in words.
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 24;
+
+/// How an instruction parameter is interpreted.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParameterMode {
+    /// The parameter is the address of the value.
+    Position,
+    /// The parameter is the value itself.
+    Immediate,
+    /// The parameter is an address offset from the machine's relative base.
+    Relative,
+}
+
+/// The modes encoded in a raw instruction, first parameter first. Each digit
+/// above the two opcode digits is one parameter's mode.
+fn mode_digits(raw_opcode: usize) -> impl Iterator<Item = Result<ParameterMode, u8>> {
+    std::iter::successors(Some(raw_opcode / 100), |rest| Some(rest / 10))
+        .take_while(|&rest| rest > 0)
+        .map(|rest| match (rest % 10) as u8 {
+            0 => Ok(ParameterMode::Position),
+            1 => Ok(ParameterMode::Immediate),
+            2 => Ok(ParameterMode::Relative),
+            digit => Err(digit),
+        })
+}
+
+/// Splits a raw instruction into its opcode and the modes of its parameters.
+///
+/// Modes are listed in parameter order; any parameter without an explicit
+/// mode digit is in position mode (see `get_mode`). Fails with the offending
+/// digit if a mode isn't one of the known modes.
+pub fn decode_opcode(raw_opcode: usize) -> Result<(usize, Vec<ParameterMode>), u8> {
+    let modes = mode_digits(raw_opcode).collect::<Result<_, _>>()?;
+    Ok((raw_opcode % 100, modes))
+}
+
+/// An instruction's opcode and parameter modes in a compact form that's
+/// cheap to copy and cache.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DecodedInstruction {
+    pub opcode: usize,
+    /// The modes of the first three parameters, which is as many as any
+    /// instruction has.
+    pub modes: [ParameterMode; 3],
+}
+
+/// Like `decode_opcode`, but without allocating. Mode digits past the third
+/// are still checked, but otherwise ignored.
+pub fn decode(raw_opcode: usize) -> Result<DecodedInstruction, u8> {
+    let mut modes = [ParameterMode::Position; 3];
+
+    for (index, mode) in mode_digits(raw_opcode).enumerate() {
+        let mode = mode?;
+
+        if let Some(slot) = modes.get_mut(index) {
+            *slot = mode;
+        }
+    }
+
+    Ok(DecodedInstruction {
+        opcode: raw_opcode % 100,
+        modes,
+    })
+}
+
+/// Returns the mode of the parameter at `index`, defaulting to position mode.
+pub fn get_mode(modes: &[ParameterMode], index: usize) -> ParameterMode {
+    *modes.get(index).unwrap_or(&ParameterMode::Position)
+}
+
+/// Static facts about one instruction in the instruction set.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OpcodeInfo {
+    pub opcode: usize,
+    pub mnemonic: &'static str,
+    pub parameter_count: usize,
+    /// The index of the parameter the instruction writes through, if any.
+    pub write_parameter: Option<usize>,
+}
+
+/// Every instruction the machine understands.
+pub const OPCODES: [OpcodeInfo; 10] = [
+    OpcodeInfo {
+        opcode: 1,
+        mnemonic: "ADD",
+        parameter_count: 3,
+        write_parameter: Some(2),
+    },
+    OpcodeInfo {
+        opcode: 2,
+        mnemonic: "MUL",
+        parameter_count: 3,
+        write_parameter: Some(2),
+    },
+    OpcodeInfo {
+        opcode: 3,
+        mnemonic: "IN",
+        parameter_count: 1,
+        write_parameter: Some(0),
+    },
+    OpcodeInfo {
+        opcode: 4,
+        mnemonic: "OUT",
+        parameter_count: 1,
+        write_parameter: None,
+    },
+    OpcodeInfo {
+        opcode: 5,
+        mnemonic: "JNZ",
+        parameter_count: 2,
+        write_parameter: None,
+    },
+    OpcodeInfo {
+        opcode: 6,
+        mnemonic: "JZ",
+        parameter_count: 2,
+        write_parameter: None,
+    },
+    OpcodeInfo {
+        opcode: 7,
+        mnemonic: "LT",
+        parameter_count: 3,
+        write_parameter: Some(2),
+    },
+    OpcodeInfo {
+        opcode: 8,
+        mnemonic: "EQ",
+        parameter_count: 3,
+        write_parameter: Some(2),
+    },
+    OpcodeInfo {
+        opcode: 9,
+        mnemonic: "ARB",
+        parameter_count: 1,
+        write_parameter: None,
+    },
+    OpcodeInfo {
+        opcode: 99,
+        mnemonic: "HLT",
+        parameter_count: 0,
+        write_parameter: None,
+    },
+];
+
+/// Looks up an opcode in the instruction set.
+pub fn opcode_info(opcode: usize) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.opcode == opcode)
+}
+
+/// Parses a comma-separated Intcode program.
+pub fn parse_program(source: &str) -> Vec<i64> {
+    source
+        .trim()
+        .split(',')
+        .map(|o| o.trim().parse::<i64>().expect("could not parse to number"))
+        .collect()
+}
+
+/// A value a machine's memory can hold. Machines normally work in `i64`;
+/// `bigint::BigInt` lifts the limit on how big values can get.
+///
+/// The default value must be zero, which is what memory past the end of a
+/// program holds.
+pub trait Word: Clone + Ord + Default + fmt::Debug + fmt::Display {
+    fn from_i64(value: i64) -> Self;
+
+    /// The value as an `i64`, if it fits.
+    fn to_i64(&self) -> Option<i64>;
+
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_sub(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+
+    /// Bits that stand for the value in hashes of memory. Equal values must
+    /// give equal bits.
+    fn hash_bits(&self) -> u64;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The value as an `i64`, clamped to the nearest one if it doesn't fit.
+    /// Errors report instructions and addresses this way.
+    fn saturating_i64(&self) -> i64 {
+        match self.to_i64() {
+            Some(value) => value,
+            None if *self < Self::default() => i64::MIN,
+            None => i64::MAX,
+        }
+    }
+}
+
+impl Word for i64 {
+    fn from_i64(value: i64) -> i64 {
+        value
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+
+    fn checked_add(&self, other: &i64) -> Option<i64> {
+        i64::checked_add(*self, *other)
+    }
+
+    fn checked_sub(&self, other: &i64) -> Option<i64> {
+        i64::checked_sub(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &i64) -> Option<i64> {
+        i64::checked_mul(*self, *other)
+    }
+
+    fn hash_bits(&self) -> u64 {
+        *self as u64
+    }
+}
+
+/// Why a machine stopped executing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Status<W = i64> {
+    /// The machine executed a halt instruction and will not run again.
+    Halted,
+    /// The machine is waiting on an input instruction; push a value and
+    /// resume it to continue.
+    NeedsInput,
+    /// The machine just output this value.
+    Output(W),
+}
+
+/// A failed instruction. Every variant records the program counter and the
+/// raw instruction value found there.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VmError {
+    /// The instruction's opcode isn't part of the instruction set.
+    UnknownOpcode { pc: usize, instruction: i64 },
+    /// One of the instruction's mode digits isn't a known parameter mode.
+    InvalidMode {
+        pc: usize,
+        instruction: i64,
+        mode: u8,
+    },
+    /// The instruction writes through an immediate-mode parameter.
+    ImmediateWrite { pc: usize, instruction: i64 },
+    /// The instruction reads from beyond the memory limit.
+    OutOfBoundsRead {
+        pc: usize,
+        instruction: i64,
+        address: usize,
+    },
+    /// The instruction writes beyond the memory limit.
+    OutOfBoundsWrite {
+        pc: usize,
+        instruction: i64,
+        address: usize,
+    },
+    /// The instruction computed a negative address to read, write or jump to.
+    NegativeAddress {
+        pc: usize,
+        instruction: i64,
+        address: i64,
+    },
+    /// The instruction wanted input and none was available.
+    InputExhausted { pc: usize, instruction: i64 },
+    /// The machine had already executed its maximum number of instructions.
+    StepLimitExceeded {
+        pc: usize,
+        instruction: i64,
+        limit: u64,
+    },
+    /// The machine came back to a state it was in `period` instructions
+    /// earlier without any input or output, so it would loop forever.
+    InfiniteLoop {
+        pc: usize,
+        instruction: i64,
+        period: u64,
+    },
+    /// The instruction computed a value, an address or a relative base that
+    /// doesn't fit in 64 bits.
+    Overflow { pc: usize, instruction: i64 },
+}
+
+impl VmError {
+    /// The address of the failed instruction.
+    pub fn program_counter(&self) -> usize {
+        match *self {
+            VmError::UnknownOpcode { pc, .. }
+            | VmError::InvalidMode { pc, .. }
+            | VmError::ImmediateWrite { pc, .. }
+            | VmError::OutOfBoundsRead { pc, .. }
+            | VmError::OutOfBoundsWrite { pc, .. }
+            | VmError::NegativeAddress { pc, .. }
+            | VmError::InputExhausted { pc, .. }
+            | VmError::StepLimitExceeded { pc, .. }
+            | VmError::InfiniteLoop { pc, .. }
+            | VmError::Overflow { pc, .. } => pc,
+        }
+    }
+
+    /// The raw value of the failed instruction.
+    pub fn instruction(&self) -> i64 {
+        match *self {
+            VmError::UnknownOpcode { instruction, .. }
+            | VmError::InvalidMode { instruction, .. }
+            | VmError::ImmediateWrite { instruction, .. }
+            | VmError::OutOfBoundsRead { instruction, .. }
+            | VmError::OutOfBoundsWrite { instruction, .. }
+            | VmError::NegativeAddress { instruction, .. }
+            | VmError::InputExhausted { instruction, .. }
+            | VmError::StepLimitExceeded { instruction, .. }
+            | VmError::InfiniteLoop { instruction, .. }
+            | VmError::Overflow { instruction, .. } => instruction,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::UnknownOpcode { .. } => write!(f, "unknown opcode")?,
+            VmError::InvalidMode { mode, .. } => write!(f, "invalid parameter mode {}", mode)?,
+            VmError::ImmediateWrite { .. } => write!(f, "write parameter in immediate mode")?,
+            VmError::OutOfBoundsRead { address, .. } => {
+                write!(f, "read from out of bounds address {}", address)?
+            }
+            VmError::OutOfBoundsWrite { address, .. } => {
+                write!(f, "write to out of bounds address {}", address)?
+            }
+            VmError::NegativeAddress { address, .. } => write!(f, "negative address {}", address)?,
+            VmError::InputExhausted { .. } => write!(f, "input exhausted")?,
+            VmError::StepLimitExceeded { limit, .. } => {
+                write!(f, "step limit of {} instructions exceeded", limit)?
+            }
+            VmError::InfiniteLoop { period, .. } => write!(
+                f,
+                "stuck in a loop of {} instructions with no input or output",
+                period
+            )?,
+            VmError::Overflow { .. } => write!(f, "arithmetic overflow")?,
+        }
+
+        write!(
+            f,
+            " (instruction {} at program counter {})",
+            self.instruction(),
+            self.program_counter()
+        )
+    }
+}
+
+impl Error for VmError {}
+
+/// A memory cell changed by an instruction.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemoryWrite<W = i64> {
+    pub address: usize,
+    pub old_value: W,
+    pub new_value: W,
+}
+
+/// Everything a single executed instruction did.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceEvent<W = i64> {
+    pub pc: usize,
+    pub instruction: i64,
+    pub opcode: usize,
+    /// The instruction's parameters with their modes applied. Parameters
+    /// that are written through resolve to the address written.
+    pub operands: Vec<W>,
+    /// The addresses read through position and relative mode parameters,
+    /// in parameter order.
+    pub reads: Vec<usize>,
+    pub write: Option<MemoryWrite<W>>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+}
+
+/// Observes every instruction a machine executes.
+pub trait Tracer<W = i64> {
+    /// Whether the tracer wants events at all. Machines don't bother
+    /// building events for tracers that don't.
+    const ENABLED: bool = true;
+
+    fn trace(&mut self, event: &TraceEvent<W>);
+}
+
+/// The tracer used when nobody is watching.
+impl<W> Tracer<W> for () {
+    const ENABLED: bool = false;
+
+    fn trace(&mut self, _event: &TraceEvent<W>) {}
+}
+
+/// Lets two tracers watch the same run.
+impl<W, A: Tracer<W>, B: Tracer<W>> Tracer<W> for (A, B) {
+    const ENABLED: bool = A::ENABLED || B::ENABLED;
+
+    fn trace(&mut self, event: &TraceEvent<W>) {
+        self.0.trace(event);
+        self.1.trace(event);
+    }
+}
+
+/// A tracer that may or may not be there.
+impl<W, T: Tracer<W>> Tracer<W> for Option<T> {
+    const ENABLED: bool = T::ENABLED;
+
+    fn trace(&mut self, event: &TraceEvent<W>) {
+        if let Some(tracer) = self {
+            tracer.trace(event);
+        }
+    }
+}
+
+/// An Intcode computer whose memory holds `W`s, which are 64-bit integers
+/// unless another word type is chosen.
+#[derive(Clone, Debug)]
+pub struct Machine<W = i64> {
+    memory: Vec<W>,
+    program_counter: usize,
+    relative_base: W,
+    input: VecDeque<W>,
+    output: Vec<W>,
+    halted: bool,
+    memory_limit: usize,
+    step_limit: Option<u64>,
+    steps: u64,
+    /// Instructions decoded so far, by address. An entry is dropped whenever
+    /// the word at its address is written.
+    decoded: Vec<Option<DecodedInstruction>>,
+    cache_decoding: bool,
+    cycles: Option<CycleDetector<W>>,
+}
+
+impl Machine {
+    /// Creates a machine whose memory is a copy of `program`.
+    pub fn new(program: &[i64]) -> Machine {
+        Machine::from_words(program)
+    }
+}
+
+impl<W: Word> Machine<W> {
+    /// Creates a machine of any word type whose memory is a copy of
+    /// `program`.
+    pub fn from_words(program: &[W]) -> Machine<W> {
+        Machine {
+            memory: program.to_vec(),
+            program_counter: 0,
+            relative_base: W::default(),
+            input: VecDeque::new(),
+            output: Vec::new(),
+            halted: false,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            step_limit: None,
+            steps: 0,
+            decoded: Vec::new(),
+            cache_decoding: true,
+            cycles: None,
+        }
+    }
+
+    /// Sets the number of words of memory the program may address.
+    pub fn set_memory_limit(&mut self, words: usize) {
+        self.memory_limit = words;
+    }
+
+    /// Sets the maximum number of instructions the machine will execute, or
+    /// removes the cap if `limit` is `None`.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// Turns the cache of decoded instructions on or off. It's on by
+    /// default; turning it off is only useful for measuring what it saves.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache_decoding = enabled;
+        self.decoded.clear();
+    }
+
+    /// Turns detection of infinite loops on or off. With it on, a machine
+    /// that returns to an earlier state without reading input or writing
+    /// output in between fails with `VmError::InfiniteLoop`. Loops that keep
+    /// changing memory, such as a counter that never stops, aren't caught;
+    /// a step limit is the backstop for those.
+    pub fn set_cycle_detection(&mut self, enabled: bool) {
+        self.cycles = if enabled {
+            Some(CycleDetector::new(&self.memory))
+        } else {
+            None
+        };
+    }
+
+    /// The number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Queues a value to be consumed by the next input instruction.
+    pub fn push_input(&mut self, value: W) {
+        self.input.push_back(value);
+    }
+
+    /// Reads the value stored at `address`. Memory past the end of the
+    /// program reads as zero.
+    pub fn read(&self, address: usize) -> W {
+        self.memory.get(address).cloned().unwrap_or_default()
+    }
+
+    /// Stores `value` at `address`, growing memory if needed.
+    pub fn write(&mut self, address: usize, value: W) {
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, W::default());
+        }
+
+        if let Some(cycles) = &mut self.cycles {
+            cycles.written(address, &self.memory[address], &value);
+        }
+
+        self.memory[address] = value;
+
+        if let Some(decoded) = self.decoded.get_mut(address) {
+            *decoded = None;
+        }
+    }
+
+    /// The machine's memory, up to the highest address written so far.
+    pub fn memory(&self) -> &[W] {
+        &self.memory
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    /// The base address used by relative-mode parameters.
+    pub fn relative_base(&self) -> W {
+        self.relative_base.clone()
+    }
+
+    /// Every value output so far, oldest first.
+    pub fn output(&self) -> &[W] {
+        &self.output
+    }
+
+    /// Removes and returns every value output so far.
+    pub fn take_output(&mut self) -> Vec<W> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Whether the machine has executed a halt instruction (or run off the
+    /// end of memory).
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    fn to_address(&self, instruction: i64, address: &W) -> Result<usize, VmError> {
+        match address.to_i64() {
+            Some(address) if address >= 0 => Ok(address as usize),
+            // Too big for any memory limit
+            None if *address > W::default() => Ok(usize::MAX),
+            _ => Err(VmError::NegativeAddress {
+                pc: self.program_counter,
+                instruction,
+                address: address.saturating_i64(),
+            }),
+        }
+    }
+
+    fn load(&self, instruction: i64, address: usize) -> Result<W, VmError> {
+        if address >= self.memory_limit {
+            return Err(VmError::OutOfBoundsRead {
+                pc: self.program_counter,
+                instruction,
+                address,
+            });
+        }
+
+        Ok(self.read(address))
+    }
+
+    fn store(&mut self, instruction: i64, address: usize, value: W) -> Result<(), VmError> {
+        if address >= self.memory_limit {
+            return Err(VmError::OutOfBoundsWrite {
+                pc: self.program_counter,
+                instruction,
+                address,
+            });
+        }
+
+        self.write(address, value);
+        Ok(())
+    }
+
+    fn parameter(
+        &self,
+        instruction: i64,
+        modes: &[ParameterMode],
+        index: usize,
+    ) -> Result<W, VmError> {
+        let value = self.load(instruction, self.program_counter + index + 1)?;
+
+        match get_mode(modes, index) {
+            ParameterMode::Immediate => Ok(value),
+            ParameterMode::Position => {
+                self.load(instruction, self.to_address(instruction, &value)?)
+            }
+            ParameterMode::Relative => {
+                self.load(instruction, self.relative_address(instruction, &value)?)
+            }
+        }
+    }
+
+    /// The addresses the current instruction's parameters read from, not
+    /// counting immediate parameters or the one it writes through. Any
+    /// parameter that can't be resolved is left out; running the instruction
+    /// reports the problem.
+    fn read_addresses(
+        &self,
+        instruction: i64,
+        opcode: usize,
+        modes: &[ParameterMode],
+    ) -> Vec<usize> {
+        let info = match opcode_info(opcode) {
+            Some(info) => info,
+            None => return Vec::new(),
+        };
+
+        (0..info.parameter_count)
+            .filter(|&index| Some(index) != info.write_parameter)
+            .filter_map(|index| {
+                let value = self
+                    .load(instruction, self.program_counter + index + 1)
+                    .ok()?;
+
+                match get_mode(modes, index) {
+                    ParameterMode::Immediate => None,
+                    ParameterMode::Position => self.to_address(instruction, &value).ok(),
+                    ParameterMode::Relative => self.relative_address(instruction, &value).ok(),
+                }
+            })
+            .collect()
+    }
+
+    fn target(
+        &self,
+        instruction: i64,
+        modes: &[ParameterMode],
+        index: usize,
+    ) -> Result<usize, VmError> {
+        let value = self.load(instruction, self.program_counter + index + 1)?;
+
+        match get_mode(modes, index) {
+            ParameterMode::Immediate => Err(VmError::ImmediateWrite {
+                pc: self.program_counter,
+                instruction,
+            }),
+            ParameterMode::Position => self.to_address(instruction, &value),
+            ParameterMode::Relative => self.relative_address(instruction, &value),
+        }
+    }
+
+    fn relative_address(&self, instruction: i64, offset: &W) -> Result<usize, VmError> {
+        let address = self.checked(instruction, self.relative_base.checked_add(offset))?;
+        self.to_address(instruction, &address)
+    }
+
+    /// Unwraps the result of checked arithmetic, failing the instruction if
+    /// it overflowed.
+    fn checked(&self, instruction: i64, value: Option<W>) -> Result<W, VmError> {
+        value.ok_or(VmError::Overflow {
+            pc: self.program_counter,
+            instruction,
+        })
+    }
+
+    /// Executes a single instruction, returning a status if that instruction
+    /// halted the machine, produced output or is blocked on input.
+    ///
+    /// A blocked input instruction is not consumed: the program counter stays
+    /// put so that the instruction runs again once input is available. The
+    /// same goes for a failed instruction, which leaves the machine exactly
+    /// as it was before the step.
+    pub fn step(&mut self) -> Result<Option<Status<W>>, VmError> {
+        self.step_traced(&mut ())
+    }
+
+    /// Like `step`, but reports the executed instruction to `tracer`.
+    /// Instructions that block on input or fail aren't reported.
+    pub fn step_traced<T: Tracer<W>>(
+        &mut self,
+        tracer: &mut T,
+    ) -> Result<Option<Status<W>>, VmError> {
+        if self.halted {
+            return Ok(Some(Status::Halted));
+        }
+
+        let pc = self.program_counter;
+
+        if pc >= self.memory.len() {
+            self.halted = true;
+            return Ok(Some(Status::Halted));
+        }
+
+        let instruction = self.memory[pc].saturating_i64();
+
+        if let Some(limit) = self.step_limit {
+            if self.steps >= limit {
+                return Err(VmError::StepLimitExceeded {
+                    pc,
+                    instruction,
+                    limit,
+                });
+            }
+        }
+
+        if let Some(cycles) = &mut self.cycles {
+            if let Some(period) = cycles.check(pc, &self.relative_base, &self.memory) {
+                return Err(VmError::InfiniteLoop {
+                    pc,
+                    instruction,
+                    period,
+                });
+            }
+        }
+
+        let unknown_opcode = VmError::UnknownOpcode { pc, instruction };
+        let DecodedInstruction { opcode, modes } = self.decode_at(pc)?;
+
+        // Reads are worked out up front, while the program counter still
+        // points at the instruction
+        let reads = if T::ENABLED {
+            self.read_addresses(instruction, opcode, &modes)
+        } else {
+            Vec::new()
+        };
+
+        // Only gathered for the tracer
+        let mut operands: [W; 3] = Default::default();
+        let mut input = None;
+        let mut output = None;
+
+        let mut talked = false;
+        let mut write = None;
+
+        let status = match opcode {
+            1 | 2 | 7 | 8 => {
+                let lhs = self.parameter(instruction, &modes, 0)?;
+                let rhs = self.parameter(instruction, &modes, 1)?;
+                let target_location = self.target(instruction, &modes, 2)?;
+
+                let value = match opcode {
+                    1 => self.checked(instruction, lhs.checked_add(&rhs))?,
+                    2 => self.checked(instruction, lhs.checked_mul(&rhs))?,
+                    7 => W::from_i64((lhs < rhs) as i64),
+                    8 => W::from_i64((lhs == rhs) as i64),
+                    _ => unreachable!(),
+                };
+
+                write = self.traced_store(T::ENABLED, instruction, target_location, value)?;
+                self.program_counter += 4;
+
+                if T::ENABLED {
+                    operands = [lhs, rhs, W::from_i64(target_location as i64)];
+                }
+
+                None
+            }
+            3 => {
+                let target_location = self.target(instruction, &modes, 0)?;
+                let value = match self.input.front() {
+                    Some(value) => value.clone(),
+                    None => {
+                        // The instruction didn't run, so the state it was
+                        // checked in mustn't count when it runs after all
+                        if let Some(cycles) = &mut self.cycles {
+                            cycles.reset();
+                        }
+                        return Ok(Some(Status::NeedsInput));
+                    }
+                };
+
+                if T::ENABLED {
+                    operands[0] = W::from_i64(target_location as i64);
+                    input = Some(value.clone());
+                }
+
+                write = self.traced_store(T::ENABLED, instruction, target_location, value)?;
+                self.input.pop_front();
+                self.program_counter += 2;
+                talked = true;
+                None
+            }
+            4 => {
+                let value = self.parameter(instruction, &modes, 0)?;
+                self.output.push(value.clone());
+                self.program_counter += 2;
+                talked = true;
+
+                if T::ENABLED {
+                    operands[0] = value.clone();
+                    output = Some(value.clone());
+                }
+
+                Some(Status::Output(value))
+            }
+            5 | 6 => {
+                let test = self.parameter(instruction, &modes, 0)?;
+                let new_location = self.parameter(instruction, &modes, 1)?;
+
+                if test.is_zero() == (opcode == 6) {
+                    self.program_counter = self.to_address(instruction, &new_location)?;
+                } else {
+                    self.program_counter += 3;
+                }
+
+                if T::ENABLED {
+                    operands[0] = test;
+                    operands[1] = new_location;
+                }
+
+                None
+            }
+            9 => {
+                let offset = self.parameter(instruction, &modes, 0)?;
+                self.relative_base =
+                    self.checked(instruction, self.relative_base.checked_add(&offset))?;
+                self.program_counter += 2;
+
+                if T::ENABLED {
+                    operands[0] = offset;
+                }
+
+                None
+            }
+            99 => {
+                self.halted = true;
+                Some(Status::Halted)
+            }
+            _ => return Err(unknown_opcode),
+        };
+
+        self.steps += 1;
+
+        if let Some(cycles) = &mut self.cycles {
+            if talked {
+                cycles.reset();
+            }
+        }
+
+        // Building the event allocates, so don't unless someone's listening
+        if T::ENABLED {
+            let operand_count = opcode_info(opcode).map_or(0, |info| info.parameter_count);
+            let mut operands = Vec::from(operands);
+            operands.truncate(operand_count);
+
+            tracer.trace(&TraceEvent {
+                pc,
+                instruction,
+                opcode,
+                operands,
+                reads,
+                write,
+                input,
+                output,
+            });
+        }
+
+        Ok(status)
+    }
+
+    /// Decodes the instruction at `pc`, reusing the last decoding of that
+    /// address if the word hasn't been written since.
+    fn decode_at(&mut self, pc: usize) -> Result<DecodedInstruction, VmError> {
+        if let Some(&Some(decoded)) = self.decoded.get(pc) {
+            return Ok(decoded);
+        }
+
+        let word = &self.memory[pc];
+        let instruction = word.saturating_i64();
+
+        let raw = match word.to_i64() {
+            Some(raw) if raw >= 0 => raw as usize,
+            _ => return Err(VmError::UnknownOpcode { pc, instruction }),
+        };
+
+        let decoded = decode(raw).map_err(|mode| VmError::InvalidMode {
+            pc,
+            instruction,
+            mode,
+        })?;
+
+        if !self.cache_decoding {
+            return Ok(decoded);
+        }
+
+        if pc >= self.decoded.len() {
+            self.decoded.resize(self.memory.len(), None);
+        }
+
+        self.decoded[pc] = Some(decoded);
+        Ok(decoded)
+    }
+
+    /// Stores a value, also describing the write for a tracer if `traced`.
+    fn traced_store(
+        &mut self,
+        traced: bool,
+        instruction: i64,
+        address: usize,
+        value: W,
+    ) -> Result<Option<MemoryWrite<W>>, VmError> {
+        if !traced {
+            self.store(instruction, address, value)?;
+            return Ok(None);
+        }
+
+        let old_value = self.read(address);
+        self.store(instruction, address, value.clone())?;
+
+        Ok(Some(MemoryWrite {
+            address,
+            old_value,
+            new_value: value,
+        }))
+    }
+
+    /// Reverses the effects of an executed instruction, putting the machine
+    /// back the way it was just before `event`.
+    ///
+    /// `event` must be the most recent instruction the machine executed that
+    /// hasn't already been undone. A value it output is taken back off the
+    /// output buffer, unless it's already been taken off with `take_output`.
+    /// Memory that grew to fit a write keeps its new size.
+    pub fn undo(&mut self, event: &TraceEvent<W>) {
+        if let Some(write) = &event.write {
+            self.write(write.address, write.old_value.clone());
+        }
+
+        if let Some(input) = &event.input {
+            self.input.push_front(input.clone());
+        }
+
+        if event.output.is_some() && self.output.last() == event.output.as_ref() {
+            self.output.pop();
+        }
+
+        if event.opcode == 9 {
+            self.relative_base = self
+                .relative_base
+                .checked_sub(&event.operands[0])
+                .expect("the instruction added this offset without overflowing");
+        }
+
+        self.program_counter = event.pc;
+        self.halted = false;
+        self.steps = self.steps.saturating_sub(1);
+
+        // The states seen since the last input or output aren't known any
+        // more, so start watching afresh
+        if let Some(cycles) = &mut self.cycles {
+            cycles.reset();
+        }
+    }
+
+    /// Executes instructions until the machine halts, outputs a value or
+    /// needs input it doesn't have.
+    pub fn resume(&mut self) -> Result<Status<W>, VmError> {
+        self.resume_traced(&mut ())
+    }
+
+    /// Like `resume`, but reports every executed instruction to `tracer`.
+    pub fn resume_traced(&mut self, tracer: &mut impl Tracer<W>) -> Result<Status<W>, VmError> {
+        loop {
+            if let Some(status) = self.step_traced(tracer)? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Executes instructions until the machine halts. Fails with
+    /// `VmError::InputExhausted` if the program asks for more input than was
+    /// pushed.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.run_traced(&mut ())
+    }
+
+    /// Like `run`, but reports every executed instruction to `tracer`.
+    pub fn run_traced(&mut self, tracer: &mut impl Tracer<W>) -> Result<(), VmError> {
+        loop {
+            match self.resume_traced(tracer)? {
+                Status::Halted => return Ok(()),
+                Status::NeedsInput => {
+                    return Err(VmError::InputExhausted {
+                        pc: self.program_counter,
+                        instruction: self.read(self.program_counter).saturating_i64(),
+                    })
+                }
+                Status::Output(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_opcode() {
+        assert_eq!(
+            decode_opcode(1002),
+            Ok((2, vec![ParameterMode::Position, ParameterMode::Immediate]))
+        );
+        assert_eq!(
+            decode_opcode(21101),
+            Ok((
+                1,
+                vec![
+                    ParameterMode::Immediate,
+                    ParameterMode::Immediate,
+                    ParameterMode::Relative
+                ]
+            ))
+        );
+        assert_eq!(decode_opcode(99), Ok((99, vec![])));
+        assert_eq!(decode_opcode(3), Ok((3, vec![])));
+        assert_eq!(decode_opcode(1301), Err(3));
+    }
+
+    #[test]
+    fn test_decode() {
+        let decoded = decode(21101).unwrap();
+        assert_eq!(decoded.opcode, 1);
+        assert_eq!(
+            decoded.modes,
+            [
+                ParameterMode::Immediate,
+                ParameterMode::Immediate,
+                ParameterMode::Relative
+            ]
+        );
+        assert_eq!(decode(4).unwrap().modes, [ParameterMode::Position; 3]);
+        assert_eq!(decode(1301), Err(3));
+        assert_eq!(decode(501_102), Err(5));
+    }
+
+    #[test]
+    fn test_decode_cache_invalidated_by_writes() {
+        // Adds two numbers and outputs the sum, then switches its first
+        // instruction to immediate mode and runs it again
+        let mut machine = Machine::new(&[
+            1, 16, 17, 18, 4, 18, 1101, 1100, 1, 0, 1105, 1, 0, 99, 0, 0, 5, 6, 0,
+        ]);
+        assert_eq!(machine.resume(), Ok(Status::Output(11)));
+        assert_eq!(machine.resume(), Ok(Status::Output(33)));
+    }
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(parse_program("1,0,0,3,99\n"), vec![1, 0, 0, 3, 99]);
+        assert_eq!(parse_program("3,-1"), vec![3, -1]);
+    }
+
+    #[test]
+    fn test_negative_immediate() {
+        let mut machine = Machine::new(&[1101, 100, -1, 4, 0]);
+        machine.run().unwrap();
+        assert_eq!(machine.memory(), &[1101, 100, -1, 4, 99]);
+    }
+
+    #[test]
+    fn test_input_output() {
+        let mut machine = Machine::new(&[3, 0, 4, 0, 99]);
+        machine.push_input(42);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[42]);
+        assert!(machine.is_halted());
+    }
+
+    #[test]
+    fn test_quine() {
+        let program = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut machine = Machine::new(&program);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &program[..]);
+        assert_eq!(machine.relative_base(), 16);
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        let mut machine = Machine::new(&[1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0]);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[1_219_070_632_396_864]);
+
+        let mut machine = Machine::new(&[104, 1_125_899_906_842_624, 99]);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[1_125_899_906_842_624]);
+    }
+
+    #[test]
+    fn test_memory_growth() {
+        // Writes input to address 1000 relative to a base of 50 and echoes it
+        let mut machine = Machine::new(&[109, 50, 203, 950, 4, 1000, 99]);
+        machine.push_input(7);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[7]);
+        assert_eq!(machine.memory().len(), 1001);
+        assert_eq!(machine.read(999), 0);
+        assert_eq!(machine.read(5000), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let run = |program: &[i64]| Machine::new(program).run();
+
+        assert_eq!(
+            run(&[1, 0, 0, 0, 42]),
+            Err(VmError::UnknownOpcode {
+                pc: 4,
+                instruction: 42
+            })
+        );
+        assert_eq!(
+            run(&[-1]),
+            Err(VmError::UnknownOpcode {
+                pc: 0,
+                instruction: -1
+            })
+        );
+        assert_eq!(
+            run(&[301, 0, 0, 0, 99]),
+            Err(VmError::InvalidMode {
+                pc: 0,
+                instruction: 301,
+                mode: 3
+            })
+        );
+        assert_eq!(
+            run(&[11101, 1, 1, 0, 99]),
+            Err(VmError::ImmediateWrite {
+                pc: 0,
+                instruction: 11101
+            })
+        );
+        assert_eq!(
+            run(&[4, -5, 99]),
+            Err(VmError::NegativeAddress {
+                pc: 0,
+                instruction: 4,
+                address: -5
+            })
+        );
+        assert_eq!(
+            run(&[3, 0, 99]),
+            Err(VmError::InputExhausted {
+                pc: 0,
+                instruction: 3
+            })
+        );
+
+        let mut machine = Machine::new(&[1101, 1, 1, 100, 4, 200, 99]);
+        machine.set_memory_limit(150);
+        assert_eq!(
+            machine.run(),
+            Err(VmError::OutOfBoundsRead {
+                pc: 4,
+                instruction: 4,
+                address: 200
+            })
+        );
+
+        let mut machine = Machine::new(&[1101, 1, 1, 100, 99]);
+        machine.set_memory_limit(50);
+        assert_eq!(
+            machine.run(),
+            Err(VmError::OutOfBoundsWrite {
+                pc: 0,
+                instruction: 1101,
+                address: 100
+            })
+        );
+
+        let mut machine = Machine::new(&[1105, 1, 0]);
+        machine.set_step_limit(Some(10));
+        assert_eq!(
+            machine.run(),
+            Err(VmError::StepLimitExceeded {
+                pc: 0,
+                instruction: 1105,
+                limit: 10
+            })
+        );
+        assert_eq!(machine.steps(), 10);
+    }
+
+    #[test]
+    fn test_error_display() {
+        let error = VmError::InvalidMode {
+            pc: 12,
+            instruction: 301,
+            mode: 3,
+        };
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter mode 3 (instruction 301 at program counter 12)"
+        );
+    }
+
+    #[test]
+    fn test_resume() {
+        // Doubles every input until it sees a zero
+        let mut machine = Machine::new(&[
+            3, 15, 1006, 15, 14, 1002, 15, 2, 15, 4, 15, 1105, 1, 0, 99, 0,
+        ]);
+        assert_eq!(machine.resume().unwrap(), Status::NeedsInput);
+        assert_eq!(machine.resume().unwrap(), Status::NeedsInput);
+
+        machine.push_input(21);
+        assert_eq!(machine.resume().unwrap(), Status::Output(42));
+        assert_eq!(machine.resume().unwrap(), Status::NeedsInput);
+
+        machine.push_input(5);
+        machine.push_input(0);
+        assert_eq!(machine.resume().unwrap(), Status::Output(10));
+        assert_eq!(machine.resume().unwrap(), Status::Halted);
+        assert_eq!(machine.take_output(), vec![42, 10]);
+        assert!(machine.output().is_empty());
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        // Example feedback loop program from day 7, phase settings 9,8,7,6,5
+        let program = parse_program(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,\
+             27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        );
+        let mut amplifiers: Vec<Machine> = [9, 8, 7, 6, 5]
+            .iter()
+            .map(|&phase| {
+                let mut machine = Machine::new(&program);
+                machine.push_input(phase);
+                machine
+            })
+            .collect();
+
+        let mut signal = 0;
+        let mut last_thruster_signal = None;
+
+        'feedback: loop {
+            for (index, amplifier) in amplifiers.iter_mut().enumerate() {
+                amplifier.push_input(signal);
+
+                match amplifier.resume().unwrap() {
+                    Status::Output(value) => signal = value,
+                    Status::Halted => break 'feedback,
+                    Status::NeedsInput => panic!("amplifier {} stalled", index),
+                }
+            }
+
+            last_thruster_signal = Some(signal);
+        }
+
+        assert_eq!(last_thruster_signal, Some(139_629_729));
+    }
+}
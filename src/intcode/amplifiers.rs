@@ -0,0 +1,255 @@
+//! Chains of Intcode machines wired output-to-input.
+//!
+//! Each machine in a chain gets its phase setting as its first input. The
+//! first machine is then given the initial signal, and every value a machine
+//! outputs becomes input for the next one. In a feedback loop the last
+//! machine's output goes back to the first; otherwise it leaves the chain as
+//! the thruster signal.
+
+use super::{Machine, Status, VmError};
+use std::error::Error;
+use std::fmt;
+
+/// A reason a chain couldn't produce a thruster signal.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ChainError {
+    /// One of the machines failed.
+    Machine { index: usize, error: VmError },
+    /// Every machine that hasn't halted is waiting for input nobody will
+    /// send.
+    Deadlock,
+    /// The chain halted without the last machine outputting anything.
+    NoOutput,
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::Machine { index, error } => write!(f, "machine {}: {}", index, error),
+            ChainError::Deadlock => write!(f, "every machine is waiting for input"),
+            ChainError::NoOutput => write!(f, "the chain produced no output"),
+        }
+    }
+}
+
+impl Error for ChainError {}
+
+/// A series of machines running copies of the same program.
+#[derive(Clone, Debug)]
+pub struct AmplifierChain {
+    machines: Vec<Machine>,
+    feedback: bool,
+}
+
+impl AmplifierChain {
+    /// Creates one machine per phase setting. If `feedback` is set, the last
+    /// machine's output is routed back to the first.
+    pub fn new(program: &[i64], phases: &[i64], feedback: bool) -> AmplifierChain {
+        let machines = phases
+            .iter()
+            .map(|&phase| {
+                let mut machine = Machine::new(program);
+                machine.push_input(phase);
+                machine
+            })
+            .collect();
+
+        AmplifierChain { machines, feedback }
+    }
+
+    /// Feeds `signal` to the first machine and runs every machine until they
+    /// have all halted, returning the last value the last machine output.
+    pub fn run(&mut self, signal: i64) -> Result<i64, ChainError> {
+        let count = self.machines.len();
+        let mut last_output = None;
+
+        match self.machines.first_mut() {
+            Some(first) => first.push_input(signal),
+            None => return Err(ChainError::NoOutput),
+        }
+
+        // Run each machine in turn until it blocks, passing its output along.
+        // A full round where nothing runs means nobody can make progress.
+        loop {
+            let mut progressed = false;
+
+            for index in 0..count {
+                let machine = &mut self.machines[index];
+                let steps = machine.steps();
+                let mut outputs = Vec::new();
+
+                loop {
+                    match machine.resume() {
+                        Ok(Status::Output(value)) => outputs.push(value),
+                        Ok(_) => break,
+                        Err(error) => return Err(ChainError::Machine { index, error }),
+                    }
+                }
+
+                progressed |= machine.steps() != steps;
+
+                if index + 1 == count {
+                    if let Some(&value) = outputs.last() {
+                        last_output = Some(value);
+                    }
+                }
+
+                let next = if index + 1 < count {
+                    Some(index + 1)
+                } else if self.feedback {
+                    Some(0)
+                } else {
+                    None
+                };
+
+                if let Some(next) = next {
+                    for value in outputs {
+                        self.machines[next].push_input(value);
+                    }
+                }
+            }
+
+            if self.machines.iter().all(|m| m.is_halted()) {
+                return last_output.ok_or(ChainError::NoOutput);
+            }
+
+            if !progressed {
+                return Err(ChainError::Deadlock);
+            }
+        }
+    }
+}
+
+/// Every ordering of `values`.
+pub fn permutations(values: &[i64]) -> Vec<Vec<i64>> {
+    if values.len() <= 1 {
+        return vec![values.to_vec()];
+    }
+
+    let mut result = Vec::new();
+
+    for (index, &first) in values.iter().enumerate() {
+        let mut rest = values.to_vec();
+        rest.remove(index);
+
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, first);
+            result.push(permutation);
+        }
+    }
+
+    result
+}
+
+/// Tries every ordering of `phases` and returns the highest thruster signal
+/// along with the phase settings that produced it.
+pub fn max_thruster_signal(
+    program: &[i64],
+    phases: &[i64],
+    feedback: bool,
+) -> Result<(i64, Vec<i64>), ChainError> {
+    let mut best: Option<(i64, Vec<i64>)> = None;
+
+    for permutation in permutations(phases) {
+        let signal = AmplifierChain::new(program, &permutation, feedback).run(0)?;
+
+        if best
+            .as_ref()
+            .is_none_or(|(best_signal, _)| signal > *best_signal)
+        {
+            best = Some((signal, permutation));
+        }
+    }
+
+    best.ok_or(ChainError::NoOutput)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::parse_program;
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(
+            permutations(&[1, 2, 3]),
+            vec![
+                vec![1, 2, 3],
+                vec![1, 3, 2],
+                vec![2, 1, 3],
+                vec![2, 3, 1],
+                vec![3, 1, 2],
+                vec![3, 2, 1],
+            ]
+        );
+        assert_eq!(permutations(&[0, 1, 2, 3, 4]).len(), 120);
+    }
+
+    #[test]
+    fn test_series() {
+        let program = parse_program("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+        assert_eq!(
+            AmplifierChain::new(&program, &[4, 3, 2, 1, 0], false).run(0),
+            Ok(43210)
+        );
+        assert_eq!(
+            max_thruster_signal(&program, &[0, 1, 2, 3, 4], false),
+            Ok((43210, vec![4, 3, 2, 1, 0]))
+        );
+
+        let program = parse_program(
+            "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0",
+        );
+        assert_eq!(
+            max_thruster_signal(&program, &[0, 1, 2, 3, 4], false),
+            Ok((54321, vec![0, 1, 2, 3, 4]))
+        );
+    }
+
+    #[test]
+    fn test_feedback() {
+        let program = parse_program(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        );
+        assert_eq!(
+            max_thruster_signal(&program, &[5, 6, 7, 8, 9], true),
+            Ok((139_629_729, vec![9, 8, 7, 6, 5]))
+        );
+
+        let program = parse_program(
+            "3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,\
+             -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,\
+             53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10",
+        );
+        assert_eq!(
+            max_thruster_signal(&program, &[5, 6, 7, 8, 9], true),
+            Ok((18216, vec![9, 7, 8, 5, 6]))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        // Waits for a second input that never comes
+        let program = [3, 0, 3, 0, 99];
+        assert_eq!(
+            AmplifierChain::new(&program, &[1, 2], false).run(0),
+            Err(ChainError::Deadlock)
+        );
+
+        assert_eq!(
+            AmplifierChain::new(&[3, 0, 99], &[1], false).run(0),
+            Err(ChainError::NoOutput)
+        );
+
+        assert_eq!(
+            AmplifierChain::new(&[3, 0, 3, 0, 42], &[1], false).run(0),
+            Err(ChainError::Machine {
+                index: 0,
+                error: VmError::UnknownOpcode {
+                    pc: 4,
+                    instruction: 42
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Text input and output for ASCII-speaking programs.
+//!
+//! Some programs read their input as character codes, a line at a time with
+//! each line ending in a newline (10), and write text back the same way.
+//! Values outside the ASCII range can't be text; they're usually the answer
+//! the program has computed, so they're kept as numbers.
+
+use super::{Machine, Status, VmError};
+use std::io::{self, BufRead, Write};
+
+/// Whether `value` is an ASCII character code.
+pub fn is_ascii(value: i64) -> bool {
+    (0..=0x7f).contains(&value)
+}
+
+/// Queues `line` as input, followed by a newline.
+pub fn push_line(machine: &mut Machine, line: &str) {
+    for c in line.chars().chain(Some('\n')) {
+        machine.push_input(c as i64);
+    }
+}
+
+/// A program's output split into text and the values that weren't text.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Rendered {
+    pub text: String,
+    pub values: Vec<i64>,
+}
+
+/// Splits output into text and numbers, keeping each in order.
+pub fn render(output: &[i64]) -> Rendered {
+    let mut rendered = Rendered::default();
+
+    for &value in output {
+        if is_ascii(value) {
+            rendered.text.push(value as u8 as char);
+        } else {
+            rendered.values.push(value);
+        }
+    }
+
+    rendered
+}
+
+/// Feeds `lines` to the machine and runs it until it halts, rendering
+/// everything it outputs along the way.
+pub fn run_lines(machine: &mut Machine, lines: &[&str]) -> Result<Rendered, VmError> {
+    for line in lines {
+        push_line(machine, line);
+    }
+
+    machine.run()?;
+    Ok(render(&machine.take_output()))
+}
+
+/// Connects the machine to a terminal: text it outputs is written as it
+/// arrives, and each time it wants input a line is read from `input`. Values
+/// that aren't text are written on a line of their own. Stops when the
+/// machine halts or fails, or `input` runs out.
+pub fn play(
+    machine: &mut Machine,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    loop {
+        match machine.resume() {
+            Ok(Status::Output(value)) if is_ascii(value) => {
+                write!(output, "{}", value as u8 as char)?;
+            }
+            Ok(Status::Output(value)) => writeln!(output, "{}", value)?,
+            Ok(Status::NeedsInput) => {
+                output.flush()?;
+
+                let mut line = String::new();
+                if input.read_line(&mut line)? == 0 {
+                    break;
+                }
+
+                push_line(machine, line.trim_end_matches(['\r', '\n']));
+            }
+            Ok(Status::Halted) => break,
+            Err(error) => {
+                writeln!(output, "Program failed: {}", error)?;
+                break;
+            }
+        }
+    }
+
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    /// Counts the characters on a line, then reports back in words and with
+    /// the count plus 1000.
+    const COUNTER: &str = "
+    loop:   IN [char]
+            EQ [char], #10, [scratch]
+            JNZ [scratch], #done
+            ADD [count], #1, [count]
+            JZ #0, #loop
+    done:   ADD [count], #1000, [count]
+            OUT #79     ; O
+            OUT #75     ; K
+            OUT #10
+            OUT [count]
+            HLT
+    char:    DATA 0
+    count:   DATA 0
+    scratch: DATA 0
+    ";
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(&[72, 105, 10, 1_000_000, 33]),
+            Rendered {
+                text: "Hi\n!".to_string(),
+                values: vec![1_000_000]
+            }
+        );
+        assert!(is_ascii(0) && is_ascii(127));
+        assert!(!is_ascii(128) && !is_ascii(-1));
+    }
+
+    #[test]
+    fn test_run_lines() {
+        let program = assemble(COUNTER).unwrap();
+        let mut machine = Machine::new(&program);
+
+        assert_eq!(
+            run_lines(&mut machine, &["hello"]).unwrap(),
+            Rendered {
+                text: "OK\n".to_string(),
+                values: vec![1005]
+            }
+        );
+    }
+
+    #[test]
+    fn test_play() {
+        // Echoes every character it reads
+        let program = assemble("loop: IN [9]\nOUT [9]\nJZ #0, #loop").unwrap();
+        let mut machine = Machine::new(&program);
+        let mut output = Vec::new();
+
+        play(&mut machine, &b"north\ntake lamp\n"[..], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "north\ntake lamp\n");
+
+        let program = assemble(COUNTER).unwrap();
+        let mut machine = Machine::new(&program);
+        let mut output = Vec::new();
+
+        play(&mut machine, &b"abc\n"[..], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "OK\n1003\n");
+        assert!(machine.is_halted());
+    }
+}
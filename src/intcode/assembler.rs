@@ -0,0 +1,457 @@
+//! Assembles Intcode programs from text.
+//!
+//! The syntax mirrors the disassembler's listings:
+//!
+//! ```text
+//! ; Outputs 1 if the input is 8, otherwise 0
+//! start:  IN [value]
+//!         EQ [value], #8, [value]
+//!         OUT [value]
+//!         HLT
+//! value:  DATA 0
+//! ```
+//!
+//! Operands are written `[address]` for position mode, `#value` for immediate
+//! mode and `rb+offset` for relative mode. Position and immediate operands and
+//! `DATA` values may name a label instead of a number, optionally with an
+//! offset (`[table+2]`).
+//! Mnemonics and directives are case-insensitive, and everything after a `;`
+//! is a comment.
+
+use super::{OpcodeInfo, ParameterMode, OPCODES};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// A problem with a line of assembly. Line numbers start at 1.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AssembleError {
+    /// The line names an instruction or directive that doesn't exist.
+    UnknownMnemonic { line: usize, mnemonic: String },
+    /// The instruction was given the wrong number of operands.
+    WrongOperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// An operand or data value couldn't be parsed.
+    InvalidOperand { line: usize, operand: String },
+    /// The instruction writes through an immediate-mode operand.
+    ImmediateWrite { line: usize },
+    /// The same label was defined twice.
+    DuplicateLabel { line: usize, label: String },
+    /// A label was used but never defined.
+    UndefinedLabel { line: usize, label: String },
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown mnemonic {}", line, mnemonic)
+            }
+            AssembleError::WrongOperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} operands, found {}",
+                line, expected, found
+            ),
+            AssembleError::InvalidOperand { line, operand } => {
+                write!(f, "line {}: invalid operand {}", line, operand)
+            }
+            AssembleError::ImmediateWrite { line } => {
+                write!(f, "line {}: write operand in immediate mode", line)
+            }
+            AssembleError::DuplicateLabel { line, label } => {
+                write!(f, "line {}: label {} is already defined", line, label)
+            }
+            AssembleError::UndefinedLabel { line, label } => {
+                write!(f, "line {}: undefined label {}", line, label)
+            }
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Value {
+    Number(i64),
+    Label { name: String, offset: i64 },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Operand {
+    mode: ParameterMode,
+    value: Value,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Statement {
+    Instruction {
+        info: &'static OpcodeInfo,
+        operands: Vec<Operand>,
+    },
+    Data(Vec<Value>),
+}
+
+impl Statement {
+    fn len(&self) -> usize {
+        match self {
+            Statement::Instruction { info, .. } => 1 + info.parameter_count,
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(line: usize, text: &str) -> Result<Value, AssembleError> {
+    let invalid = || AssembleError::InvalidOperand {
+        line,
+        operand: text.to_string(),
+    };
+    let text = text.trim();
+
+    if let Ok(number) = text.parse::<i64>() {
+        return Ok(Value::Number(number));
+    }
+
+    // A label, possibly followed by +N or -N
+    let (name, offset) = match text.find(['+', '-']) {
+        Some(split) => {
+            let offset = text[split..]
+                .trim_start_matches('+')
+                .parse::<i64>()
+                .map_err(|_| invalid())?;
+            (text[..split].trim_end(), offset)
+        }
+        None => (text, 0),
+    };
+
+    if !is_identifier(name) {
+        return Err(invalid());
+    }
+
+    Ok(Value::Label {
+        name: name.to_string(),
+        offset,
+    })
+}
+
+fn parse_operand(line: usize, text: &str) -> Result<Operand, AssembleError> {
+    let invalid = || AssembleError::InvalidOperand {
+        line,
+        operand: text.to_string(),
+    };
+
+    if let Some(value) = text.strip_prefix('#') {
+        Ok(Operand {
+            mode: ParameterMode::Immediate,
+            value: parse_value(line, value)?,
+        })
+    } else if let Some(address) = text.strip_prefix('[') {
+        let address = address.strip_suffix(']').ok_or_else(invalid)?;
+
+        Ok(Operand {
+            mode: ParameterMode::Position,
+            value: parse_value(line, address)?,
+        })
+    } else if let Some(offset) = text.strip_prefix("rb") {
+        let offset = offset.trim();
+        let offset = if offset.is_empty() {
+            0
+        } else if offset.starts_with('+') || offset.starts_with('-') {
+            offset
+                .trim_start_matches('+')
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| invalid())?
+        } else {
+            return Err(invalid());
+        };
+
+        Ok(Operand {
+            mode: ParameterMode::Relative,
+            value: Value::Number(offset),
+        })
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_statement(line: usize, text: &str) -> Result<Statement, AssembleError> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(split) => (&text[..split], text[split..].trim()),
+        None => (text, ""),
+    };
+    let arguments: Vec<&str> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(|a| a.trim()).collect()
+    };
+
+    if mnemonic.eq_ignore_ascii_case("data") {
+        let values = arguments
+            .iter()
+            .map(|a| parse_value(line, a))
+            .collect::<Result<_, _>>()?;
+
+        return Ok(Statement::Data(values));
+    }
+
+    let info = OPCODES
+        .iter()
+        .find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic))
+        .ok_or_else(|| AssembleError::UnknownMnemonic {
+            line,
+            mnemonic: mnemonic.to_string(),
+        })?;
+
+    if arguments.len() != info.parameter_count {
+        return Err(AssembleError::WrongOperandCount {
+            line,
+            expected: info.parameter_count,
+            found: arguments.len(),
+        });
+    }
+
+    let operands: Vec<Operand> = arguments
+        .iter()
+        .map(|a| parse_operand(line, a))
+        .collect::<Result<_, _>>()?;
+
+    if let Some(write_parameter) = info.write_parameter {
+        if operands[write_parameter].mode == ParameterMode::Immediate {
+            return Err(AssembleError::ImmediateWrite { line });
+        }
+    }
+
+    Ok(Statement::Instruction { info, operands })
+}
+
+fn resolve(
+    labels: &HashMap<String, usize>,
+    line: usize,
+    value: &Value,
+) -> Result<i64, AssembleError> {
+    match value {
+        Value::Number(number) => Ok(*number),
+        Value::Label { name, offset } => {
+            let address = labels
+                .get(name)
+                .ok_or_else(|| AssembleError::UndefinedLabel {
+                    line,
+                    label: name.clone(),
+                })?;
+
+            (*address as i64)
+                .checked_add(*offset)
+                .ok_or_else(|| AssembleError::InvalidOperand {
+                    line,
+                    operand: format!("{}{:+}", name, offset),
+                })
+        }
+    }
+}
+
+/// Assembles `source` into a program.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = text.split(';').next().unwrap().trim();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+
+            if !is_identifier(label) {
+                break;
+            }
+
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AssembleError::DuplicateLabel {
+                    line,
+                    label: label.to_string(),
+                });
+            }
+
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line, text)?;
+        address += statement.len();
+        statements.push((line, statement));
+    }
+
+    let mut program = Vec::with_capacity(address);
+
+    for (line, statement) in &statements {
+        match statement {
+            Statement::Instruction { info, operands } => {
+                let mut instruction = info.opcode as i64;
+                let mut place = 100;
+
+                for operand in operands {
+                    let mode = match operand.mode {
+                        ParameterMode::Position => 0,
+                        ParameterMode::Immediate => 1,
+                        ParameterMode::Relative => 2,
+                    };
+
+                    instruction += mode * place;
+                    place *= 10;
+                }
+
+                program.push(instruction);
+
+                for operand in operands {
+                    program.push(resolve(&labels, *line, &operand.value)?);
+                }
+            }
+            Statement::Data(values) => {
+                for value in values {
+                    program.push(resolve(&labels, *line, value)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    #[test]
+    fn test_assemble_day_five_samples() {
+        let source = "
+            IN [input]
+            EQ [input], [eight], [input]   ; position mode comparison
+            OUT [input]
+            HLT
+            input: DATA -1
+            eight: DATA 8
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8])
+        );
+
+        let source = "
+            in [input]
+            lt [input], #8, [input]
+            out [input]
+            hlt
+            input: data 0
+        ";
+        assert_eq!(assemble(source), Ok(vec![3, 9, 1007, 9, 8, 9, 4, 9, 99, 0]));
+    }
+
+    #[test]
+    fn test_labels_and_relative_mode() {
+        // Counts down from 3, outputting each value
+        let source = "
+            ARB #counter
+        loop:
+            OUT rb+0
+            ADD rb, #-1, rb+0
+            JNZ rb+0, #loop
+            HLT
+        counter: DATA 3
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            vec![109, 12, 204, 0, 21201, 0, -1, 0, 1205, 0, 2, 99, 3]
+        );
+
+        let mut machine = Machine::new(&program);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[3, 2, 1]);
+    }
+
+    #[test]
+    fn test_label_offsets() {
+        let source = "
+            OUT [table+1]
+            OUT #table-1
+            HLT
+        table: DATA 10, 20, table
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, vec![4, 6, 104, 4, 99, 10, 20, 5]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("NOP"),
+            Err(AssembleError::UnknownMnemonic {
+                line: 1,
+                mnemonic: "NOP".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("\nADD #1, #2"),
+            Err(AssembleError::WrongOperandCount {
+                line: 2,
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            assemble("OUT 5"),
+            Err(AssembleError::InvalidOperand {
+                line: 1,
+                operand: "5".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("IN #5"),
+            Err(AssembleError::ImmediateWrite { line: 1 })
+        );
+        assert_eq!(
+            assemble("a: HLT\na: HLT"),
+            Err(AssembleError::DuplicateLabel {
+                line: 2,
+                label: "a".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("OUT #end+9223372036854775807\nend: HLT"),
+            Err(AssembleError::InvalidOperand {
+                line: 1,
+                operand: "end+9223372036854775807".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("JNZ #1, #nowhere"),
+            Err(AssembleError::UndefinedLabel {
+                line: 1,
+                label: "nowhere".to_string()
+            })
+        );
+    }
+}
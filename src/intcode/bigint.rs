@@ -0,0 +1,473 @@
+//! Running Intcode programs with arbitrary-precision integers.
+//!
+//! `Machine` works in 64 bits and fails with `VmError::Overflow` when a
+//! value doesn't fit. Programs that really do need bigger numbers can run on
+//! a `BigMachine` instead, which is a `Machine` whose memory holds `BigInt`s.
+//! It runs the same interpreter, so tracing, step limits and loop detection
+//! all work, but snapshots and compilation are still 64-bit only.
+//!
+//! Addresses and instructions still have to be small. `VmError` records
+//! them as 64-bit values, so an instruction word or an address too big for
+//! that is reported clamped to the nearest value that fits.
+
+use super::{Machine, Word};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, Mul};
+use std::str::FromStr;
+
+/// An integer of any size.
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct BigInt {
+    negative: bool,
+    /// Base 2^32 digits, least significant first, with no trailing zeros.
+    /// Zero has no digits and is never negative.
+    magnitude: Vec<u32>,
+}
+
+fn trim(magnitude: &mut Vec<u32>) {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+
+    for index in 0..a.len().max(b.len()) {
+        let digit =
+            u64::from(*a.get(index).unwrap_or(&0)) + u64::from(*b.get(index).unwrap_or(&0)) + carry;
+        sum.push(digit as u32);
+        carry = digit >> 32;
+    }
+
+    sum.push(carry as u32);
+    trim(&mut sum);
+    sum
+}
+
+/// Subtracts `b` from `a`, which must be at least as large.
+fn subtract_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+
+    for (index, &digit) in a.iter().enumerate() {
+        let subtrahend = i64::from(*b.get(index).unwrap_or(&0)) + borrow;
+        let mut digit = i64::from(digit) - subtrahend;
+        borrow = 0;
+
+        if digit < 0 {
+            digit += 1 << 32;
+            borrow = 1;
+        }
+
+        difference.push(digit as u32);
+    }
+
+    trim(&mut difference);
+    difference
+}
+
+fn multiply_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0u32; a.len() + b.len()];
+
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+
+        for (j, &y) in b.iter().enumerate() {
+            let digit = u64::from(x) * u64::from(y) + u64::from(product[i + j]) + carry;
+            product[i + j] = digit as u32;
+            carry = digit >> 32;
+        }
+
+        product[i + b.len()] = carry as u32;
+    }
+
+    trim(&mut product);
+    product
+}
+
+impl BigInt {
+    fn new(negative: bool, magnitude: Vec<u32>) -> BigInt {
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0u64, |value, &digit| (value << 32) | u64::from(digit));
+
+        if self.negative {
+            if magnitude <= 1 << 63 {
+                Some((magnitude as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else if magnitude < 1 << 63 {
+            Some(magnitude as i64)
+        } else {
+            None
+        }
+    }
+
+    /// Divides the magnitude by `divisor` in place, returning the remainder.
+    fn divide_magnitude(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+        let mut remainder = 0u64;
+
+        for digit in magnitude.iter_mut().rev() {
+            let value = (remainder << 32) | u64::from(*digit);
+            *digit = (value / u64::from(divisor)) as u32;
+            remainder = value % u64::from(divisor);
+        }
+
+        trim(magnitude);
+        remainder as u32
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> BigInt {
+        let magnitude = value.unsigned_abs();
+        let mut digits = vec![magnitude as u32, (magnitude >> 32) as u32];
+        trim(&mut digits);
+        BigInt::new(value < 0, digits)
+    }
+}
+
+impl Word for BigInt {
+    fn from_i64(value: i64) -> BigInt {
+        BigInt::from(value)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        BigInt::to_i64(self)
+    }
+
+    fn checked_add(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self + other)
+    }
+
+    fn checked_sub(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self + &BigInt::new(!other.negative, other.magnitude.clone()))
+    }
+
+    fn checked_mul(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self * other)
+    }
+
+    fn hash_bits(&self) -> u64 {
+        self.magnitude
+            .iter()
+            .fold(self.negative as u64, |hash, &digit| {
+                hash.rotate_left(32) ^ u64::from(digit)
+            })
+    }
+
+    fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(
+                self.negative,
+                add_magnitudes(&self.magnitude, &other.magnitude),
+            );
+        }
+
+        match compare_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(
+                other.negative,
+                subtract_magnitudes(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::new(
+                self.negative,
+                subtract_magnitudes(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(
+            self.negative != other.negative,
+            multiply_magnitudes(&self.magnitude, &other.magnitude),
+        )
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Peel off nine decimal digits at a time
+        let mut magnitude = self.magnitude.clone();
+        let mut chunks = Vec::new();
+
+        while !magnitude.is_empty() {
+            chunks.push(BigInt::divide_magnitude(&mut magnitude, 1_000_000_000));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A string that isn't a decimal integer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseBigIntError(pub String);
+
+impl fmt::Display for ParseBigIntError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid integer {:?}", self.0)
+    }
+}
+
+impl Error for ParseBigIntError {}
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(source: &str) -> Result<BigInt, ParseBigIntError> {
+        let (negative, digits) = match source.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, source),
+        };
+
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseBigIntError(source.to_string()));
+        }
+
+        let mut magnitude = Vec::new();
+
+        for digit in digits.bytes() {
+            let mut carry = u64::from(digit - b'0');
+
+            for word in magnitude.iter_mut() {
+                let value = u64::from(*word) * 10 + carry;
+                *word = value as u32;
+                carry = value >> 32;
+            }
+
+            if carry != 0 {
+                magnitude.push(carry as u32);
+            }
+        }
+
+        Ok(BigInt::new(negative, magnitude))
+    }
+}
+
+/// Like `intcode::parse_program`, but for programs whose values may not fit
+/// in 64 bits.
+pub fn parse_program(source: &str) -> Vec<BigInt> {
+    source
+        .trim()
+        .split(',')
+        .map(|o| o.trim().parse().expect("could not parse to number"))
+        .collect()
+}
+
+/// An Intcode machine whose memory holds integers of any size.
+pub type BigMachine = Machine<BigInt>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::trace::TraceRecorder;
+    use crate::intcode::{Status, VmError};
+
+    fn big(source: &str) -> BigInt {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = big("18446744073709551616");
+        let b = big("-340282366920938463463374607431768211456");
+
+        assert_eq!(
+            (&a * &a).to_string(),
+            "340282366920938463463374607431768211456"
+        );
+        assert_eq!(&(&a * &a) + &b, BigInt::default());
+        assert_eq!(
+            (&a + &b).to_string(),
+            "-340282366920938463444927863358058659840"
+        );
+        assert_eq!((&BigInt::from(-5) * &BigInt::from(7)).to_string(), "-35");
+        assert!(b < BigInt::from(i64::MIN) && BigInt::from(i64::MAX) < a);
+    }
+
+    #[test]
+    fn test_conversions() {
+        for &value in &[0, 1, -1, 1_000_000_000, i64::MAX, i64::MIN] {
+            assert_eq!(BigInt::from(value).to_i64(), Some(value));
+            assert_eq!(big(&value.to_string()), BigInt::from(value));
+            assert_eq!(BigInt::from(value).to_string(), value.to_string());
+        }
+
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").saturating_i64(), i64::MIN);
+        assert_eq!(big("-0"), BigInt::default());
+        assert!("12a".parse::<BigInt>().is_err());
+        assert!("-".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn test_matches_machine() {
+        // Day 5's comparison sample
+        let program = [
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        let big_program: Vec<BigInt> = program.iter().map(|&value| BigInt::from(value)).collect();
+
+        for input in 6..11 {
+            let mut machine = Machine::new(&program);
+            let mut big_machine = BigMachine::from_words(&big_program);
+            machine.push_input(input);
+            big_machine.push_input(BigInt::from(input));
+
+            machine.run().unwrap();
+            big_machine.run().unwrap();
+            assert_eq!(big_machine.output(), &[BigInt::from(machine.output()[0])]);
+            assert_eq!(big_machine.steps(), machine.steps());
+        }
+    }
+
+    #[test]
+    fn test_beyond_64_bits() {
+        // Squares 2^62, then uses the relative base to output it
+        let program =
+            parse_program("1102,4611686018427387904,4611686018427387904,9,109,9,204,0,99,0");
+        let mut machine = BigMachine::from_words(&program);
+        machine.run().unwrap();
+        assert_eq!(
+            machine.output(),
+            &[big("21267647932558653966460912964485513216")]
+        );
+
+        // The same program overflows the 64 bit machine
+        let program: Vec<i64> = vec![1102, 1 << 62, 1 << 62, 9, 109, 9, 204, 0, 99, 0];
+        assert_eq!(
+            Machine::new(&program).run(),
+            Err(VmError::Overflow {
+                pc: 0,
+                instruction: 1102
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut machine = BigMachine::from_words(&parse_program("4,-1"));
+        assert_eq!(
+            machine.run(),
+            Err(VmError::NegativeAddress {
+                pc: 0,
+                instruction: 4,
+                address: -1
+            })
+        );
+
+        let mut machine = BigMachine::from_words(&parse_program("104,1,99999999999999999999"));
+        assert_eq!(machine.resume(), Ok(Status::Output(BigInt::from(1))));
+        assert_eq!(
+            machine.run(),
+            Err(VmError::UnknownOpcode {
+                pc: 2,
+                instruction: i64::MAX
+            })
+        );
+    }
+
+    #[test]
+    fn test_machine_options() {
+        // Doubles a value past 64 bits, then spins forever
+        let program = parse_program("1002,7,2,7,1105,1,4,9223372036854775807");
+
+        let mut machine = BigMachine::from_words(&program);
+        let mut recorder = TraceRecorder::default();
+        machine.set_step_limit(Some(3));
+        assert_eq!(
+            machine.run_traced(&mut recorder),
+            Err(VmError::StepLimitExceeded {
+                pc: 4,
+                instruction: 1105,
+                limit: 3
+            })
+        );
+        assert_eq!(machine.read(7), big("18446744073709551614"));
+        assert_eq!(
+            recorder.events[0].write.as_ref().map(|w| &w.new_value),
+            Some(&big("18446744073709551614"))
+        );
+
+        let mut machine = BigMachine::from_words(&program);
+        machine.set_cycle_detection(true);
+        assert!(matches!(
+            machine.run(),
+            Err(VmError::InfiniteLoop { pc: 4, .. })
+        ));
+    }
+}
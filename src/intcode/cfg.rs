@@ -0,0 +1,365 @@
+//! Control-flow graphs of Intcode programs.
+//!
+//! The graph is discovered statically from address 0. Straight-line runs of
+//! instructions form basic blocks, which end at a jump, a halt, or just
+//! before another block's first instruction. Only jumps to immediate
+//! addresses are followed statically. A jump to an address read from memory,
+//! whether through a position or a relative parameter, can't be resolved
+//! without running the program, since the program may rewrite the cell it
+//! reads; its block is marked as dynamic. Jumps observed in a recorded trace
+//! can fill those targets in.
+//!
+//! Graphs are exported in Graphviz's DOT format, e.g.
+//! `dot -Tsvg cfg.dot -o cfg.svg`.
+
+use super::disassembler::{decode_instruction, Instruction};
+use super::TraceEvent;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How control gets from one block to another.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum EdgeKind {
+    /// Running off the end of a block into the next one, including a
+    /// conditional jump that isn't taken.
+    Fallthrough,
+    /// A jump to an address known without running the program.
+    Jump,
+    /// A jump seen in a trace whose target is read from memory.
+    Traced,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that always execute together.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<Instruction>,
+    /// Whether the block ends in a jump whose target is read from memory.
+    pub dynamic_jump: bool,
+    /// Whether the block runs into words that aren't a valid instruction.
+    pub invalid_end: bool,
+}
+
+/// A program's basic blocks and the edges between them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub edges: BTreeSet<Edge>,
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of everything statically reachable from address 0.
+    pub fn build(memory: &[i64]) -> ControlFlowGraph {
+        ControlFlowGraph::build_with_trace(memory, &[])
+    }
+
+    /// Builds the graph, taking the targets of dynamic jumps from `trace`.
+    /// Code that's only reachable through those targets is included too.
+    pub fn build_with_trace(memory: &[i64], trace: &[TraceEvent]) -> ControlFlowGraph {
+        // Jumps actually taken in the trace, by the address of the jump
+        let mut traced: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+
+        for event in trace {
+            if let (5, &[test, destination]) | (6, &[test, destination]) =
+                (event.opcode, &event.operands[..])
+            {
+                if (test != 0) == (event.opcode == 5) && destination >= 0 {
+                    traced
+                        .entry(event.pc)
+                        .or_default()
+                        .insert(destination as usize);
+                }
+            }
+        }
+
+        // Find every reachable instruction, and which of them start blocks
+        let mut instructions = BTreeMap::new();
+        let mut invalid = BTreeSet::new();
+        let mut leaders = BTreeSet::new();
+        let mut pending = vec![0];
+        leaders.insert(0);
+
+        while let Some(address) = pending.pop() {
+            if instructions.contains_key(&address) || invalid.contains(&address) {
+                continue;
+            }
+
+            let instruction = match decode_instruction(memory, address) {
+                Some(instruction) => instruction,
+                None => {
+                    invalid.insert(address);
+                    continue;
+                }
+            };
+
+            let flow = instruction.control_flow(memory);
+            let traced_targets = traced.get(&address).filter(|_| flow.dynamic);
+            let targets = flow.jump.iter().chain(traced_targets.into_iter().flatten());
+
+            for &target in targets {
+                leaders.insert(target);
+                pending.push(target);
+            }
+
+            if let Some(fallthrough) = flow.fallthrough {
+                // A block ends at every jump, so whatever follows starts one
+                if instruction.opcode == 5 || instruction.opcode == 6 {
+                    leaders.insert(fallthrough);
+                }
+
+                pending.push(fallthrough);
+            }
+
+            instructions.insert(address, instruction);
+        }
+
+        // Group the instructions into blocks
+        let mut blocks = BTreeMap::new();
+        let mut edges = BTreeSet::new();
+
+        for &start in &leaders {
+            if !instructions.contains_key(&start) {
+                continue;
+            }
+
+            let mut block = BasicBlock {
+                start,
+                instructions: Vec::new(),
+                dynamic_jump: false,
+                invalid_end: false,
+            };
+            let mut address = start;
+
+            while let Some(instruction) = instructions.get(&address) {
+                let flow = instruction.control_flow(memory);
+                block.instructions.push(instruction.clone());
+                block.dynamic_jump |= flow.dynamic;
+
+                if let Some(target) = flow.jump {
+                    edges.insert(Edge {
+                        from: start,
+                        to: target,
+                        kind: EdgeKind::Jump,
+                    });
+                }
+
+                if flow.dynamic {
+                    for &target in traced.get(&address).into_iter().flatten() {
+                        edges.insert(Edge {
+                            from: start,
+                            to: target,
+                            kind: EdgeKind::Traced,
+                        });
+                    }
+                }
+
+                let next = match flow.fallthrough {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                if invalid.contains(&next) {
+                    block.invalid_end = true;
+                    break;
+                }
+
+                if leaders.contains(&next) {
+                    edges.insert(Edge {
+                        from: start,
+                        to: next,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                    break;
+                }
+
+                address = next;
+            }
+
+            blocks.insert(start, block);
+        }
+
+        // Edges into invalid code have nowhere to go
+        edges.retain(|edge| blocks.contains_key(&edge.to));
+
+        ControlFlowGraph { blocks, edges }
+    }
+
+    /// Renders the graph in Graphviz's DOT language. Dynamic jumps get a
+    /// dotted edge to a `?` node, and edges seen in a trace are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot =
+            String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut any_dynamic = false;
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+
+            for instruction in &block.instructions {
+                label.push_str(&format!("{}: {}\\l", instruction.address, instruction));
+            }
+
+            if block.invalid_end {
+                label.push_str("(invalid instruction)\\l");
+            }
+
+            let color = if block.dynamic_jump {
+                ", color=red"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    b{} [label=\"{}\"{}];\n",
+                block.start, label, color
+            ));
+
+            if block.dynamic_jump {
+                any_dynamic = true;
+                dot.push_str(&format!(
+                    "    b{} -> dynamic [style=dotted];\n",
+                    block.start
+                ));
+            }
+        }
+
+        if any_dynamic {
+            dot.push_str("    dynamic [label=\"?\", shape=diamond];\n");
+        }
+
+        for edge in &self.edges {
+            let attributes = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Traced => " [label=\"traced\", style=dashed]",
+            };
+
+            dot.push_str(&format!(
+                "    b{} -> b{}{};\n",
+                edge.from, edge.to, attributes
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::trace::TraceRecorder;
+    use crate::intcode::Machine;
+
+    // Outputs whether the input is nonzero, jumping to an immediate address
+    const IMMEDIATE: [i64; 14] = [3, 12, 1005, 12, 9, 1101, 0, 0, 13, 4, 13, 99, 0, 1];
+    // Day 5's version, which jumps to an address stored in memory
+    const POSITION: [i64; 16] = [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+    // The same, but reading the address through the relative base
+    const RELATIVE: [i64; 16] = [3, 12, 2006, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+
+    fn edge(from: usize, to: usize, kind: EdgeKind) -> Edge {
+        Edge { from, to, kind }
+    }
+
+    #[test]
+    fn test_blocks_and_edges() {
+        let graph = ControlFlowGraph::build(&IMMEDIATE);
+
+        assert_eq!(
+            graph.blocks.keys().copied().collect::<Vec<_>>(),
+            vec![0, 5, 9]
+        );
+        assert_eq!(graph.blocks[&0].instructions.len(), 2);
+        assert!(!graph.blocks[&0].dynamic_jump);
+        assert_eq!(
+            graph.edges.iter().copied().collect::<Vec<_>>(),
+            vec![
+                edge(0, 5, EdgeKind::Fallthrough),
+                edge(0, 9, EdgeKind::Jump),
+                edge(5, 9, EdgeKind::Fallthrough),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jumps_through_memory() {
+        // The stored target isn't trusted, since the program could change it
+        let graph = ControlFlowGraph::build(&POSITION);
+        assert!(graph.blocks[&0].dynamic_jump);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 5]);
+        assert!(!graph.edges.iter().any(|edge| edge.kind == EdgeKind::Jump));
+
+        let instruction = decode_instruction(&POSITION, 2).unwrap();
+        assert_eq!(instruction.successors(&POSITION), vec![5]);
+        assert_eq!(instruction.control_flow(&POSITION).stored_jump, Some(9));
+    }
+
+    #[test]
+    fn test_rewritten_jump_target() {
+        // Stores 10 over the 7 in [13] before jumping through it
+        let program = [1101, 0, 10, 13, 6, 14, 13, 99, 99, 99, 104, 7, 99, 7, 0];
+
+        let graph = ControlFlowGraph::build(&program);
+        assert!(graph.blocks[&0].dynamic_jump);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 7]);
+        assert_eq!(
+            graph.edges.iter().copied().collect::<Vec<_>>(),
+            vec![edge(0, 7, EdgeKind::Fallthrough)]
+        );
+
+        let mut machine = Machine::new(&program);
+        let mut recorder = TraceRecorder::default();
+        machine.run_traced(&mut recorder).unwrap();
+        assert_eq!(machine.output(), &[7]);
+
+        let graph = ControlFlowGraph::build_with_trace(&program, &recorder.events);
+        assert_eq!(
+            graph.blocks.keys().copied().collect::<Vec<_>>(),
+            vec![0, 7, 10]
+        );
+        assert_eq!(
+            graph.edges.iter().copied().collect::<Vec<_>>(),
+            vec![
+                edge(0, 7, EdgeKind::Fallthrough),
+                edge(0, 10, EdgeKind::Traced)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dynamic_jumps() {
+        let graph = ControlFlowGraph::build(&RELATIVE);
+        assert!(graph.blocks[&0].dynamic_jump);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 5]);
+
+        // The zero input takes the jump to 9, which can then be followed
+        let mut machine = Machine::new(&RELATIVE);
+        let mut recorder = TraceRecorder::default();
+        machine.push_input(0);
+        machine.run_traced(&mut recorder).unwrap();
+
+        let graph = ControlFlowGraph::build_with_trace(&RELATIVE, &recorder.events);
+        assert_eq!(
+            graph.blocks.keys().copied().collect::<Vec<_>>(),
+            vec![0, 5, 9]
+        );
+        assert!(graph.edges.contains(&edge(0, 9, EdgeKind::Traced)));
+        assert!(graph.edges.contains(&edge(0, 5, EdgeKind::Fallthrough)));
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = ControlFlowGraph::build(&RELATIVE).to_dot();
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b0 [label=\"0: IN [12]\\l2: JZ [12], rb+15\\l\", color=red];\n"));
+        assert!(dot.contains("    b0 -> dynamic [style=dotted];\n"));
+        assert!(dot.contains("    b0 -> b5;\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
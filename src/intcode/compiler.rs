@@ -0,0 +1,512 @@
+//! Compiling programs to closures for fast repeated runs.
+//!
+//! The interpreter decodes an instruction's opcode and parameter modes every
+//! time it executes it. A `CompiledProgram` does that work once, up front:
+//! every instruction reachable from address 0 becomes a closure with its
+//! opcode, modes and constant operands baked in, so running it only does the
+//! arithmetic.
+//!
+//! Compiled code is only valid while the instructions it was built from stay
+//! the same. Any instruction whose words have changed, whether before the
+//! run or because the program wrote over its own code, is interpreted
+//! instead, as is anything at an address that wasn't compiled. The
+//! interpreter works on the same machine, so execution carries on exactly
+//! where compiled code left off.
+//!
+//! Some callers alter a program before each run, like day 2 setting its noun
+//! and verb. Those words can be marked as patchable when compiling: operands
+//! stored there are read from memory when the instruction runs instead of
+//! being baked in.
+
+use super::disassembler::{decode_instruction, find_instructions, Instruction};
+use super::{Machine, ParameterMode, Status, TraceEvent, Tracer, VmError};
+use std::collections::BTreeSet;
+
+/// Where a compiled instruction finds one of its parameters.
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    Immediate(i64),
+    Position(usize),
+    Relative(i64),
+    /// A patchable word, read when the instruction runs.
+    Patched {
+        word: usize,
+        mode: ParameterMode,
+    },
+}
+
+/// What the runner should do after a compiled instruction.
+enum Flow {
+    Next,
+    /// The instruction wrote to this address.
+    Wrote(usize),
+    Status(Status),
+    /// Something unusual happened, such as an out of bounds access, and the
+    /// interpreter should take over. The instruction hasn't changed the
+    /// machine.
+    Interpret,
+}
+
+type Compiled = Box<dyn Fn(&mut Machine) -> Flow + Send + Sync>;
+
+fn to_address(value: i64) -> Option<usize> {
+    if value >= 0 {
+        Some(value as usize)
+    } else {
+        None
+    }
+}
+
+fn load(machine: &Machine, address: usize) -> Option<i64> {
+    if address < machine.memory_limit {
+        Some(machine.read(address))
+    } else {
+        None
+    }
+}
+
+impl Source {
+    fn read(self, machine: &Machine) -> Option<i64> {
+        match self {
+            Source::Immediate(value) => Some(value),
+            Source::Position(address) => load(machine, address),
+            Source::Relative(offset) => load(
+                machine,
+                to_address(machine.relative_base.checked_add(offset)?)?,
+            ),
+            Source::Patched { word, mode } => {
+                let value = machine.read(word);
+
+                match mode {
+                    ParameterMode::Immediate => Some(value),
+                    ParameterMode::Position => load(machine, to_address(value)?),
+                    ParameterMode::Relative => load(
+                        machine,
+                        to_address(machine.relative_base.checked_add(value)?)?,
+                    ),
+                }
+            }
+        }
+    }
+
+    fn target(self, machine: &Machine) -> Option<usize> {
+        let address = match self {
+            Source::Immediate(_) => return None,
+            Source::Position(address) => address,
+            Source::Relative(offset) => to_address(machine.relative_base.checked_add(offset)?)?,
+            Source::Patched { word, mode } => match mode {
+                ParameterMode::Immediate => return None,
+                ParameterMode::Position => to_address(machine.read(word))?,
+                ParameterMode::Relative => {
+                    to_address(machine.relative_base.checked_add(machine.read(word))?)?
+                }
+            },
+        };
+
+        if address < machine.memory_limit {
+            Some(address)
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns a decoded instruction into a closure, or returns `None` if it's
+/// better left to the interpreter.
+fn compile_instruction(instruction: &Instruction, patchable: &BTreeSet<usize>) -> Option<Compiled> {
+    let address = instruction.address;
+    let next = address + instruction.len();
+
+    let mut sources = Vec::new();
+    for (index, operand) in instruction.operands.iter().enumerate() {
+        let word = address + index + 1;
+
+        sources.push(if patchable.contains(&word) {
+            Source::Patched {
+                word,
+                mode: operand.mode,
+            }
+        } else {
+            match operand.mode {
+                ParameterMode::Immediate => Source::Immediate(operand.value),
+                ParameterMode::Position => Source::Position(to_address(operand.value)?),
+                ParameterMode::Relative => Source::Relative(operand.value),
+            }
+        });
+    }
+
+    let compiled: Compiled = match instruction.opcode {
+        1 | 2 | 7 | 8 => {
+            let (lhs, rhs, target) = (sources[0], sources[1], sources[2]);
+            // Overflow is left to the interpreter to report
+            let operation: fn(i64, i64) -> Option<i64> = match instruction.opcode {
+                1 => i64::checked_add,
+                2 => i64::checked_mul,
+                7 => |a, b| Some((a < b) as i64),
+                _ => |a, b| Some((a == b) as i64),
+            };
+
+            Box::new(move |machine| {
+                let (lhs, rhs, target) =
+                    match (lhs.read(machine), rhs.read(machine), target.target(machine)) {
+                        (Some(lhs), Some(rhs), Some(target)) => (lhs, rhs, target),
+                        _ => return Flow::Interpret,
+                    };
+                let value = match operation(lhs, rhs) {
+                    Some(value) => value,
+                    None => return Flow::Interpret,
+                };
+
+                machine.write(target, value);
+                machine.program_counter = next;
+                machine.steps += 1;
+                Flow::Wrote(target)
+            })
+        }
+        3 => {
+            let target = sources[0];
+
+            Box::new(move |machine| {
+                let target = match target.target(machine) {
+                    Some(target) => target,
+                    None => return Flow::Interpret,
+                };
+
+                let value = match machine.input.pop_front() {
+                    Some(value) => value,
+                    None => return Flow::Status(Status::NeedsInput),
+                };
+
+                machine.write(target, value);
+                machine.program_counter = next;
+                machine.steps += 1;
+                Flow::Wrote(target)
+            })
+        }
+        4 => {
+            let source = sources[0];
+
+            Box::new(move |machine| {
+                let value = match source.read(machine) {
+                    Some(value) => value,
+                    None => return Flow::Interpret,
+                };
+
+                machine.output.push(value);
+                machine.program_counter = next;
+                machine.steps += 1;
+                Flow::Status(Status::Output(value))
+            })
+        }
+        5 | 6 => {
+            let (test, destination) = (sources[0], sources[1]);
+            let jump_if_zero = instruction.opcode == 6;
+
+            Box::new(move |machine| {
+                let (test, destination) = match (test.read(machine), destination.read(machine)) {
+                    (Some(test), Some(destination)) => (test, destination),
+                    _ => return Flow::Interpret,
+                };
+
+                if (test == 0) == jump_if_zero {
+                    machine.program_counter = match to_address(destination) {
+                        Some(destination) => destination,
+                        None => return Flow::Interpret,
+                    };
+                } else {
+                    machine.program_counter = next;
+                }
+
+                machine.steps += 1;
+                Flow::Next
+            })
+        }
+        9 => {
+            let source = sources[0];
+
+            Box::new(move |machine| {
+                let relative_base = match source.read(machine) {
+                    Some(offset) => machine.relative_base.checked_add(offset),
+                    None => None,
+                };
+
+                machine.relative_base = match relative_base {
+                    Some(relative_base) => relative_base,
+                    None => return Flow::Interpret,
+                };
+                machine.program_counter = next;
+                machine.steps += 1;
+                Flow::Next
+            })
+        }
+        _ => Box::new(|machine| {
+            machine.halted = true;
+            machine.steps += 1;
+            Flow::Status(Status::Halted)
+        }),
+    };
+
+    Some(compiled)
+}
+
+/// Remembers where an interpreted instruction wrote.
+#[derive(Default)]
+struct WriteWatch {
+    address: Option<usize>,
+}
+
+impl Tracer for WriteWatch {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.address = event.write.map(|w| w.address);
+    }
+}
+
+/// A program compiled to closures, ready to run on any number of machines.
+pub struct CompiledProgram {
+    /// The compiled instruction starting at each address, if any.
+    instructions: Vec<Option<Compiled>>,
+    /// The program the instructions were compiled from.
+    program: Vec<i64>,
+    /// For each word baked into a compiled instruction, the address of that
+    /// instruction.
+    owners: Vec<Option<usize>>,
+}
+
+impl CompiledProgram {
+    /// Compiles every instruction reachable from address 0. Operands stored
+    /// at `patchable` addresses are read at run time; an instruction whose
+    /// opcode is patchable isn't compiled at all, and neither is one that
+    /// overlaps another.
+    pub fn compile(program: &[i64], patchable: &[usize]) -> CompiledProgram {
+        let patchable: BTreeSet<usize> = patchable.iter().copied().collect();
+        let mut instructions: Vec<Option<Compiled>> = program.iter().map(|_| None).collect();
+        let mut owners = vec![None; program.len()];
+
+        for address in find_instructions(program) {
+            if patchable.contains(&address) {
+                continue;
+            }
+
+            let instruction = match decode_instruction(program, address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+
+            let words = address..address + instruction.len();
+            if owners[words.clone()].iter().any(|owner| owner.is_some()) {
+                continue;
+            }
+
+            if let Some(compiled) = compile_instruction(&instruction, &patchable) {
+                for (word, owner) in words.clone().zip(&mut owners[words]) {
+                    if !patchable.contains(&word) {
+                        *owner = Some(address);
+                    }
+                }
+
+                instructions[address] = Some(compiled);
+            }
+        }
+
+        CompiledProgram {
+            instructions,
+            program: program.to_vec(),
+            owners,
+        }
+    }
+
+    /// The number of instructions that were compiled.
+    pub fn compiled_count(&self) -> usize {
+        self.instructions.iter().filter(|i| i.is_some()).count()
+    }
+
+    /// Finds the compiled instructions whose words in the machine's memory no
+    /// longer match what they were compiled from.
+    fn stale_instructions(&self, machine: &Machine) -> Vec<bool> {
+        let mut stale = vec![false; self.instructions.len()];
+
+        for (address, owner) in self.owners.iter().enumerate() {
+            if let Some(owner) = *owner {
+                stale[owner] |= machine.read(address) != self.program[address];
+            }
+        }
+
+        stale
+    }
+
+    /// Runs `machine` like `Machine::resume`, using compiled code wherever it
+    /// can. The machine should have been loaded with the compiled program;
+    /// any instruction that has been changed since, including by the program
+    /// itself, is interpreted instead.
+    pub fn resume(&self, machine: &mut Machine) -> Result<Status, VmError> {
+        // Cycle detection needs to see every state, so it's left to the
+        // interpreter
+        if machine.cycles.is_some() {
+            return machine.resume();
+        }
+
+        let mut stale = self.stale_instructions(machine);
+
+        loop {
+            if machine.halted {
+                return Ok(Status::Halted);
+            }
+
+            // The interpreter reports the exceeded limit
+            if machine
+                .step_limit
+                .is_some_and(|limit| machine.steps >= limit)
+            {
+                return machine.resume();
+            }
+
+            let pc = machine.program_counter;
+            let compiled = match self.instructions.get(pc) {
+                Some(Some(compiled)) if !stale[pc] => compiled,
+                _ => {
+                    let mut watch = WriteWatch::default();
+                    let status = machine.step_traced(&mut watch)?;
+
+                    if let Some(Some(owner)) = watch.address.and_then(|a| self.owners.get(a)) {
+                        stale[*owner] = true;
+                    }
+
+                    match status {
+                        Some(status) => return Ok(status),
+                        None => continue,
+                    }
+                }
+            };
+
+            match compiled(machine) {
+                Flow::Next => {}
+                Flow::Wrote(address) => {
+                    if let Some(Some(owner)) = self.owners.get(address) {
+                        stale[*owner] = true;
+                    }
+                }
+                Flow::Status(status) => return Ok(status),
+                Flow::Interpret => return machine.resume(),
+            }
+        }
+    }
+
+    /// Runs `machine` like `Machine::run`, using compiled code wherever it
+    /// can.
+    pub fn run(&self, machine: &mut Machine) -> Result<(), VmError> {
+        loop {
+            match self.resume(machine)? {
+                Status::Halted => return Ok(()),
+                Status::NeedsInput => {
+                    return Err(VmError::InputExhausted {
+                        pc: machine.program_counter,
+                        instruction: machine.read(machine.program_counter),
+                    })
+                }
+                Status::Output(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `program` with `inputs` both interpreted and compiled, checking
+    /// that the two machines end up identical.
+    fn run_both(program: &[i64], patchable: &[usize], inputs: &[i64]) -> Machine {
+        let compiled = CompiledProgram::compile(program, patchable);
+        let mut interpreted = Machine::new(program);
+        let mut machine = Machine::new(program);
+
+        for &input in inputs {
+            interpreted.push_input(input);
+            machine.push_input(input);
+        }
+
+        assert_eq!(compiled.run(&mut machine), interpreted.run());
+        assert_eq!(machine.memory(), interpreted.memory());
+        assert_eq!(machine.output(), interpreted.output());
+        assert_eq!(machine.steps(), interpreted.steps());
+        assert_eq!(machine.program_counter(), interpreted.program_counter());
+        machine
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        // Day 5's comparison sample, and a quine that uses the relative base
+        let program = [
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+
+        for input in 6..11 {
+            run_both(&program, &[], &[input]);
+        }
+
+        let quine = [
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let machine = run_both(&quine, &[], &[]);
+        assert_eq!(machine.output(), &quine[..]);
+
+        let compiled = CompiledProgram::compile(&quine, &[]);
+        assert_eq!(compiled.compiled_count(), 6);
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Both leave reporting the overflow to the interpreter
+        run_both(&[1101, i64::MAX, 1, 5, 99, 0], &[], &[]);
+        run_both(&[109, i64::MIN, 109, -1, 99], &[], &[]);
+    }
+
+    #[test]
+    fn test_patchable_words() {
+        // Day 2's sample, compiled once and run with different operands
+        let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let compiled = CompiledProgram::compile(&program, &[1, 2]);
+
+        for (noun, verb) in [(9, 10), (10, 11), (5, 6)].iter() {
+            let mut machine = Machine::new(&program);
+            machine.write(1, *noun);
+            machine.write(2, *verb);
+
+            let mut interpreted = machine.clone();
+            compiled.run(&mut machine).unwrap();
+            interpreted.run().unwrap();
+            assert_eq!(machine.memory(), interpreted.memory());
+        }
+    }
+
+    #[test]
+    fn test_falls_back_on_self_modification() {
+        // Day 2's sample overwrites the operands of its own second instruction
+        let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let machine = run_both(&program, &[], &[]);
+        assert_eq!(machine.read(0), 3500);
+
+        // Rewrites its halt into an output, which has to be noticed
+        let program = [1101, 0, 104, 4, 99, 7];
+        let machine = run_both(&program, &[], &[]);
+        assert_eq!(machine.output(), &[7]);
+
+        // Instructions patched outside the patchable words are interpreted
+        let program = [1101, 1, 2, 5, 99, 0];
+        let compiled = CompiledProgram::compile(&program, &[]);
+        let mut machine = Machine::new(&program);
+        machine.write(1, 40);
+        compiled.run(&mut machine).unwrap();
+        assert_eq!(machine.read(5), 42);
+    }
+
+    #[test]
+    fn test_errors_match_interpreter() {
+        run_both(&[3, 0, 99], &[], &[]);
+        run_both(&[1, 0, 0, -1, 99], &[], &[]);
+        run_both(&[1101, 0, 0, 3, 42], &[], &[]);
+        run_both(&[109, -5, 204, 0, 99], &[], &[]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Detecting programs stuck in infinite loops.
+//!
+//! A machine that neither reads input nor writes output is deterministic, so
+//! if it ever returns to a state it has been in before (the same program
+//! counter, relative base and memory) it will go round the same loop
+//! forever. A `CycleDetector` watches for that using Brent's algorithm: it
+//! saves the state at every power of two instructions since the last input
+//! or output, and compares each later state with the last one it saved. A
+//! loop is found within a few times its length, with only one saved copy of
+//! memory at a time.
+//!
+//! Comparing whole memories on every instruction would be slow, so the
+//! detector keeps a hash of memory up to date as it's written, and only
+//! compares memory in full when the hashes agree.
+
+use super::Word;
+
+/// The part of the hash contributed by one word. Zero words contribute
+/// nothing, so memory growing doesn't change the hash.
+fn word_hash<W: Word>(address: usize, value: &W) -> u64 {
+    if value.is_zero() {
+        return 0;
+    }
+
+    // SplitMix64's finalizer
+    let mut hash = (address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ value.hash_bits();
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Whether two memories are equal, treating missing words as zero.
+fn same_memory<W: Word>(a: &[W], b: &[W]) -> bool {
+    let common = a.len().min(b.len());
+
+    a[..common] == b[..common]
+        && a[common..].iter().all(Word::is_zero)
+        && b[common..].iter().all(Word::is_zero)
+}
+
+#[derive(Clone, Debug)]
+struct Checkpoint<W> {
+    program_counter: usize,
+    relative_base: W,
+    memory_hash: u64,
+    memory: Vec<W>,
+    steps: u64,
+}
+
+/// Watches a machine for states that repeat without any input or output in
+/// between.
+#[derive(Clone, Debug)]
+pub struct CycleDetector<W = i64> {
+    memory_hash: u64,
+    checkpoint: Option<Checkpoint<W>>,
+    /// Instructions checked since the last input or output.
+    steps: u64,
+    next_checkpoint: u64,
+}
+
+impl<W: Word> CycleDetector<W> {
+    /// Starts watching a machine with the given memory.
+    pub fn new(memory: &[W]) -> CycleDetector<W> {
+        CycleDetector {
+            memory_hash: memory.iter().enumerate().fold(0, |hash, (address, value)| {
+                hash.wrapping_add(word_hash(address, value))
+            }),
+            checkpoint: None,
+            steps: 0,
+            next_checkpoint: 1,
+        }
+    }
+
+    /// Records a write to memory.
+    pub fn written(&mut self, address: usize, old_value: &W, new_value: &W) {
+        self.memory_hash = self
+            .memory_hash
+            .wrapping_sub(word_hash(address, old_value))
+            .wrapping_add(word_hash(address, new_value));
+    }
+
+    /// Records that the machine read input or wrote output, which makes
+    /// earlier states irrelevant.
+    pub fn reset(&mut self) {
+        self.checkpoint = None;
+        self.steps = 0;
+        self.next_checkpoint = 1;
+    }
+
+    /// Checks the state the machine is in before executing an instruction.
+    /// Returns the length of the loop, in instructions, if the state has
+    /// been seen before.
+    pub fn check(
+        &mut self,
+        program_counter: usize,
+        relative_base: &W,
+        memory: &[W],
+    ) -> Option<u64> {
+        self.steps += 1;
+
+        if let Some(checkpoint) = &self.checkpoint {
+            if checkpoint.program_counter == program_counter
+                && checkpoint.relative_base == *relative_base
+                && checkpoint.memory_hash == self.memory_hash
+                && same_memory(&checkpoint.memory, memory)
+            {
+                return Some(self.steps - checkpoint.steps);
+            }
+        }
+
+        if self.steps == self.next_checkpoint {
+            self.checkpoint = Some(Checkpoint {
+                program_counter,
+                relative_base: relative_base.clone(),
+                memory_hash: self.memory_hash,
+                memory: memory.to_vec(),
+                steps: self.steps,
+            });
+            self.next_checkpoint *= 2;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Machine, Status, VmError};
+
+    fn run(program: &[i64]) -> Result<(), VmError> {
+        let mut machine = Machine::new(program);
+        machine.set_cycle_detection(true);
+        machine.set_step_limit(Some(100_000));
+        machine.run()
+    }
+
+    #[test]
+    fn test_infinite_loops() {
+        assert_eq!(
+            run(&[1105, 1, 0]),
+            Err(VmError::InfiniteLoop {
+                pc: 0,
+                instruction: 1105,
+                period: 1
+            })
+        );
+
+        // Flips the sign of a word on every pass, so the state repeats every
+        // other pass
+        assert_eq!(
+            run(&[1002, 7, -1, 7, 1105, 1, 0, 5]),
+            Err(VmError::InfiniteLoop {
+                pc: 4,
+                instruction: 1105,
+                period: 4
+            })
+        );
+
+        // Memory grows on the first pass, but only with a zero
+        assert!(matches!(
+            run(&[1101, 0, 0, 100, 1105, 1, 0]),
+            Err(VmError::InfiniteLoop { period: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_loops_that_end_or_talk() {
+        // Counts to 1000 and halts
+        assert_eq!(
+            run(&[1001, 9, 1, 9, 1008, 9, 1000, 10, 1006, 10, 0, 99]),
+            Ok(())
+        );
+
+        // Counts down and halts
+        assert_eq!(run(&[1001, 10, -1, 10, 1005, 10, 0, 99, 0, 0, 500]), Ok(()));
+
+        // Outputs forever, which is only stopped by the step limit
+        assert!(matches!(
+            run(&[104, 1, 1105, 1, 0]),
+            Err(VmError::StepLimitExceeded { .. })
+        ));
+
+        // Polls for input that never comes, which the caller can see
+        let mut machine = Machine::new(&[3, 5, 1105, 1, 0, 0]);
+        machine.set_cycle_detection(true);
+        machine.push_input(1);
+        assert_eq!(machine.resume(), Ok(Status::NeedsInput));
+    }
+
+    #[test]
+    fn test_resume_after_input() {
+        let mut machine = Machine::new(&[3, 5, 4, 5, 99, 0]);
+        machine.set_cycle_detection(true);
+        assert_eq!(machine.resume(), Ok(Status::NeedsInput));
+        assert_eq!(machine.resume(), Ok(Status::NeedsInput));
+
+        machine.push_input(7);
+        assert_eq!(machine.resume(), Ok(Status::Output(7)));
+        assert_eq!(machine.resume(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn test_same_memory() {
+        assert!(same_memory(&[1, 2], &[1, 2, 0, 0]));
+        assert!(!same_memory(&[1, 2], &[1, 2, 3]));
+        assert_eq!(
+            CycleDetector::new(&[1i64, 2]).memory_hash,
+            CycleDetector::new(&[1i64, 2, 0, 0]).memory_hash
+        );
+    }
+}
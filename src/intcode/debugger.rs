@@ -0,0 +1,569 @@
+//! An interactive debugger for Intcode programs.
+//!
+//! The debugger wraps a `Machine` and reads commands one line at a time.
+//! Type `help` at the prompt for the list of commands.
+//!
+//! Everything the machine executes under the debugger is recorded in a
+//! `History`, so execution can also be stepped backwards to find out how the
+//! machine got where it is, as far back as the history's capacity allows.
+
+use super::disassembler::decode_instruction;
+use super::history::History;
+use super::snapshot;
+use super::{Machine, Status};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// The most cells a single `print` shows.
+const MAX_PRINTED_CELLS: usize = 1000;
+
+const HELP: &str = "\
+step [n]            execute n instructions (default 1)
+continue            run until a breakpoint, watchpoint, halt or missing input
+back [n]            undo n instructions (default 1)
+rcontinue           run backwards until a breakpoint or watchpoint
+who <addr>          show the last instruction that wrote to addr
+break <addr>        stop before executing the instruction at addr
+delete <addr>       remove a breakpoint or watchpoint at addr
+watch <addr>        stop after any instruction that changes memory at addr
+input <values...>   queue input values for the program
+registers           show the program counter and relative base
+print <addr> [n]    show n memory cells starting at addr (default 1, at most 1000)
+set <addr> <value>  overwrite the memory cell at addr
+list [n]            disassemble n instructions from the program counter
+save <file>         write a snapshot of the machine to file
+quit                leave the debugger";
+
+/// Why a `continue` command stopped.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Stop {
+    Breakpoint(usize),
+    Watchpoint {
+        address: usize,
+        old_value: i64,
+        new_value: i64,
+    },
+    Status(Status),
+    Error(String),
+    StartOfHistory,
+}
+
+/// A machine being debugged, along with its breakpoints and watchpoints.
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    history: History,
+}
+
+fn parse_number<T: std::str::FromStr>(argument: Option<&&str>) -> Result<T, String> {
+    let argument = argument.ok_or_else(|| "missing argument".to_string())?;
+    argument
+        .parse::<T>()
+        .map_err(|_| format!("{} is not a valid number", argument))
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Debugger {
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: History::new(),
+        }
+    }
+
+    /// The machine being debugged.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    fn watched_values(&self) -> Vec<(usize, i64)> {
+        self.watchpoints
+            .iter()
+            .map(|&address| (address, self.machine.read(address)))
+            .collect()
+    }
+
+    fn check_watchpoints(&self, watched: Vec<(usize, i64)>) -> Option<Stop> {
+        for (address, old_value) in watched {
+            let new_value = self.machine.read(address);
+
+            if new_value != old_value {
+                return Some(Stop::Watchpoint {
+                    address,
+                    old_value,
+                    new_value,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Executes one instruction, checking watchpoints afterwards.
+    fn step_once(&mut self) -> Option<Stop> {
+        let watched = self.watched_values();
+
+        let status = match self.machine.step_traced(&mut self.history) {
+            Ok(status) => status,
+            Err(error) => return Some(Stop::Error(error.to_string())),
+        };
+
+        if let Some(stop) = self.check_watchpoints(watched) {
+            return Some(stop);
+        }
+
+        match status {
+            Some(Status::Output(_)) | None => None,
+            Some(status) => Some(Stop::Status(status)),
+        }
+    }
+
+    /// Undoes one instruction, checking watchpoints afterwards.
+    fn step_back_once(&mut self) -> Option<Stop> {
+        let watched = self.watched_values();
+
+        if self.history.step_back(&mut self.machine).is_none() {
+            return Some(Stop::StartOfHistory);
+        }
+
+        self.check_watchpoints(watched)
+    }
+
+    fn describe_stop(&self, stop: &Stop) -> String {
+        match stop {
+            Stop::Breakpoint(address) => format!("breakpoint at {}", address),
+            Stop::Watchpoint {
+                address,
+                old_value,
+                new_value,
+            } => format!(
+                "watchpoint: [{}] changed from {} to {}",
+                address, old_value, new_value
+            ),
+            Stop::Status(Status::Halted) => "program halted".to_string(),
+            Stop::Status(Status::NeedsInput) => {
+                "program needs input; queue some with `input`".to_string()
+            }
+            Stop::Status(Status::Output(value)) => format!("output: {}", value),
+            Stop::Error(error) => format!("error: {}", error),
+            Stop::StartOfHistory => "reached the start of recorded history".to_string(),
+        }
+    }
+
+    fn current_instruction(&self) -> String {
+        match self.list(1).pop() {
+            Some(line) => line,
+            None => format!("{:>6}  (end of memory)", self.machine.program_counter()),
+        }
+    }
+
+    /// Collects output produced since the last command, one line per value.
+    fn drain_output(&mut self, lines: &mut Vec<String>) {
+        for value in self.machine.take_output() {
+            lines.push(format!("output: {}", value));
+        }
+    }
+
+    fn step(&mut self, count: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for _ in 0..count {
+            let stop = self.step_once();
+            self.drain_output(&mut lines);
+
+            if let Some(stop) = stop {
+                lines.push(self.describe_stop(&stop));
+                break;
+            }
+        }
+
+        lines.push(self.current_instruction());
+        lines
+    }
+
+    fn continue_execution(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        // Always execute at least one instruction so that continuing from a
+        // breakpoint doesn't immediately stop on it again.
+        let mut stop = self.step_once();
+
+        while stop.is_none() {
+            let pc = self.machine.program_counter();
+
+            if self.breakpoints.contains(&pc) {
+                stop = Some(Stop::Breakpoint(pc));
+            } else {
+                stop = self.step_once();
+            }
+        }
+
+        self.drain_output(&mut lines);
+        lines.push(self.describe_stop(&stop.unwrap()));
+        lines.push(self.current_instruction());
+        lines
+    }
+
+    fn back(&mut self, count: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for _ in 0..count {
+            if let Some(stop) = self.step_back_once() {
+                lines.push(self.describe_stop(&stop));
+                break;
+            }
+        }
+
+        lines.push(self.current_instruction());
+        lines
+    }
+
+    fn reverse_continue(&mut self) -> Vec<String> {
+        let mut stop = self.step_back_once();
+
+        while stop.is_none() {
+            let pc = self.machine.program_counter();
+
+            if self.breakpoints.contains(&pc) {
+                stop = Some(Stop::Breakpoint(pc));
+            } else {
+                stop = self.step_back_once();
+            }
+        }
+
+        vec![
+            self.describe_stop(&stop.unwrap()),
+            self.current_instruction(),
+        ]
+    }
+
+    fn who_wrote(&self, address: usize) -> Vec<String> {
+        match self.history.last_write(address) {
+            Some((index, event)) => {
+                let instruction = decode_instruction(self.machine.memory(), event.pc)
+                    .filter(|i| i.words[0] == event.instruction)
+                    .map_or_else(|| event.instruction.to_string(), |i| i.to_string());
+
+                vec![format!(
+                    "[{}] was last written by step {} at {}: {}",
+                    address,
+                    index + 1,
+                    event.pc,
+                    instruction
+                )]
+            }
+            None => vec![format!("[{}] hasn't been written since the start", address)],
+        }
+    }
+
+    fn list(&self, count: usize) -> Vec<String> {
+        let memory = self.machine.memory();
+        let mut address = self.machine.program_counter();
+        let mut lines = Vec::new();
+
+        for _ in 0..count {
+            if address >= memory.len() {
+                break;
+            }
+
+            match decode_instruction(memory, address) {
+                Some(instruction) => {
+                    lines.push(format!("{:>6}  {}", address, instruction));
+                    address += instruction.len();
+                }
+                None => {
+                    lines.push(format!("{:>6}  DATA {}", address, memory[address]));
+                    address += 1;
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// Executes a single command, returning the lines it printed. Returns
+    /// `None` if the command asks to leave the debugger.
+    pub fn execute(&mut self, command: &str) -> Option<Vec<String>> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let name = match words.first() {
+            Some(&name) => name,
+            None => return Some(Vec::new()),
+        };
+        let arguments = &words[1..];
+
+        let result: Result<Vec<String>, String> = match name {
+            "s" | "step" => match arguments.first() {
+                Some(_) => parse_number(arguments.first()).map(|count| self.step(count)),
+                None => Ok(self.step(1)),
+            },
+            "c" | "continue" => Ok(self.continue_execution()),
+            "back" => match arguments.first() {
+                Some(_) => parse_number(arguments.first()).map(|count| self.back(count)),
+                None => Ok(self.back(1)),
+            },
+            "rc" | "rcontinue" => Ok(self.reverse_continue()),
+            "who" => parse_number(arguments.first()).map(|address| self.who_wrote(address)),
+            "b" | "break" => parse_number(arguments.first()).map(|address| {
+                self.breakpoints.insert(address);
+                vec![format!("breakpoint set at {}", address)]
+            }),
+            "d" | "delete" => parse_number(arguments.first()).map(|address| {
+                let removed = self.breakpoints.remove(&address) | self.watchpoints.remove(&address);
+
+                if removed {
+                    vec![format!("removed {}", address)]
+                } else {
+                    vec![format!("nothing set at {}", address)]
+                }
+            }),
+            "w" | "watch" => parse_number(arguments.first()).map(|address| {
+                self.watchpoints.insert(address);
+                vec![format!("watching [{}]", address)]
+            }),
+            "i" | "input" => arguments
+                .iter()
+                .map(|argument| parse_number::<i64>(Some(argument)))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|values| {
+                    for &value in &values {
+                        self.machine.push_input(value);
+                    }
+
+                    vec![format!("queued {} input values", values.len())]
+                }),
+            "r" | "registers" => Ok(vec![format!(
+                "pc: {}  rb: {}  steps: {}",
+                self.machine.program_counter(),
+                self.machine.relative_base(),
+                self.machine.steps()
+            )]),
+            "p" | "print" => parse_number::<usize>(arguments.first()).and_then(|address| {
+                let count = match arguments.get(1) {
+                    Some(_) => parse_number(arguments.get(1))?,
+                    None => 1,
+                };
+
+                address.checked_add(count).ok_or_else(|| {
+                    format!("{} cells from {} is past the end of memory", count, address)
+                })?;
+
+                let shown = count.min(MAX_PRINTED_CELLS);
+                let mut lines: Vec<String> = (address..address + shown)
+                    .map(|a| format!("[{}] = {}", a, self.machine.read(a)))
+                    .collect();
+
+                if shown < count {
+                    lines.push(format!("(showing the first {} of {} cells)", shown, count));
+                }
+
+                Ok(lines)
+            }),
+            "set" => parse_number::<usize>(arguments.first()).and_then(|address| {
+                let value = parse_number::<i64>(arguments.get(1))?;
+
+                if address >= self.machine.memory_limit {
+                    return Err(format!("{} is beyond the machine's memory", address));
+                }
+
+                self.history.patch(&mut self.machine, address, value);
+                Ok(vec![format!("[{}] = {}", address, value)])
+            }),
+            "l" | "list" => match arguments.first() {
+                Some(_) => parse_number(arguments.first()).map(|count| self.list(count)),
+                None => Ok(self.list(5)),
+            },
+            "save" => match arguments.first() {
+                Some(path) => fs::write(path, snapshot::save(&self.machine))
+                    .map(|_| vec![format!("saved snapshot to {}", path)])
+                    .map_err(|error| format!("could not save snapshot: {}", error)),
+                None => Err("missing argument".to_string()),
+            },
+            "h" | "help" => Ok(HELP.lines().map(|l| l.to_string()).collect()),
+            "q" | "quit" => return None,
+            _ => Err(format!("unknown command {}; try `help`", name)),
+        };
+
+        Some(result.unwrap_or_else(|error| vec![error]))
+    }
+
+    /// Reads commands from `input` until it ends or the user quits.
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.current_instruction())?;
+        write!(output, "(icdb) ")?;
+        output.flush()?;
+
+        for command in input.lines() {
+            match self.execute(&command?) {
+                Some(lines) => {
+                    for line in lines {
+                        writeln!(output, "{}", line)?;
+                    }
+                }
+                None => break,
+            }
+
+            write!(output, "(icdb) ")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(program: &[i64]) -> Debugger {
+        Debugger::new(Machine::new(program))
+    }
+
+    #[test]
+    fn test_step_and_registers() {
+        let mut debugger = debugger(&[1101, 2, 3, 5, 99, 0]);
+        assert_eq!(
+            debugger.execute("step").unwrap(),
+            vec!["     4  HLT".to_string()]
+        );
+        assert_eq!(
+            debugger.execute("registers").unwrap(),
+            vec!["pc: 4  rb: 0  steps: 1".to_string()]
+        );
+        assert_eq!(
+            debugger.execute("print 5").unwrap(),
+            vec!["[5] = 5".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // Outputs 1, 2, 3 then halts
+        let mut debugger = debugger(&[104, 1, 104, 2, 104, 3, 99]);
+        debugger.execute("break 4");
+
+        assert_eq!(
+            debugger.execute("continue").unwrap(),
+            vec![
+                "output: 1",
+                "output: 2",
+                "breakpoint at 4",
+                "     4  OUT #3"
+            ]
+        );
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            vec!["output: 3", "program halted", "     6  HLT"]
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = debugger(&[1101, 1, 1, 9, 1101, 2, 2, 10, 99, 0, 0]);
+        debugger.execute("watch 10");
+
+        let lines = debugger.execute("continue").unwrap();
+        assert_eq!(lines[0], "watchpoint: [10] changed from 0 to 4");
+        assert_eq!(debugger.machine().program_counter(), 8);
+    }
+
+    #[test]
+    fn test_input_and_patching() {
+        let mut debugger = debugger(&[3, 0, 4, 0, 99]);
+        assert_eq!(
+            debugger.execute("c").unwrap()[0],
+            "program needs input; queue some with `input`"
+        );
+
+        debugger.execute("input 7");
+        debugger.execute("set 4 98");
+        let lines = debugger.execute("c").unwrap();
+        assert_eq!(lines[0], "output: 7");
+        assert!(lines[1].starts_with("error: unknown opcode"));
+    }
+
+    #[test]
+    fn test_rewinding_patches() {
+        let mut debugger = debugger(&[1101, 2, 3, 5, 99, 0]);
+        debugger.execute("step");
+        debugger.execute("set 5 42");
+        debugger.execute("set 0 1102");
+
+        debugger.execute("back");
+        assert_eq!(debugger.machine().read(5), 0);
+        assert_eq!(debugger.machine().read(0), 1101);
+
+        assert_eq!(debugger.execute("c").unwrap()[0], "program halted");
+        assert_eq!(debugger.machine().read(5), 5);
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        // Doubles the input twice into address 11, then outputs it
+        let mut debugger = debugger(&[3, 11, 1002, 11, 2, 11, 1002, 11, 2, 11, 99, 0]);
+        debugger.execute("input 3");
+        debugger.execute("c");
+        assert_eq!(debugger.machine().read(11), 12);
+
+        assert_eq!(
+            debugger.execute("who 11").unwrap(),
+            vec!["[11] was last written by step 3 at 6: MUL [11], #2, [11]"]
+        );
+
+        assert_eq!(
+            debugger.execute("back 2").unwrap(),
+            vec!["     6  MUL [11], #2, [11]"]
+        );
+        assert_eq!(debugger.machine().read(11), 6);
+
+        debugger.execute("watch 11");
+        let lines = debugger.execute("rcontinue").unwrap();
+        assert_eq!(lines[0], "watchpoint: [11] changed from 6 to 3");
+        assert_eq!(debugger.machine().program_counter(), 2);
+
+        assert_eq!(
+            debugger.execute("rc").unwrap()[0],
+            "watchpoint: [11] changed from 3 to 0"
+        );
+        assert_eq!(
+            debugger.execute("rc").unwrap()[0],
+            "reached the start of recorded history"
+        );
+
+        // Input consumed by the undone instruction is available again
+        debugger.execute("delete 11");
+        assert_eq!(debugger.execute("c").unwrap()[0], "program halted");
+        assert_eq!(debugger.machine().read(11), 12);
+    }
+
+    #[test]
+    fn test_list_and_errors() {
+        let mut debugger = debugger(&[1002, 4, 3, 4, 33]);
+        assert_eq!(
+            debugger.execute("list 2").unwrap(),
+            vec!["     0  MUL [4], #3, [4]", "     4  DATA 33"]
+        );
+        assert_eq!(
+            debugger.execute("break x").unwrap(),
+            vec!["x is not a valid number"]
+        );
+        assert_eq!(
+            debugger.execute("print 18446744073709551615 2").unwrap(),
+            vec!["2 cells from 18446744073709551615 is past the end of memory"]
+        );
+        let lines = debugger.execute("print 0 1000000000000").unwrap();
+        assert_eq!(lines.len(), MAX_PRINTED_CELLS + 1);
+        assert_eq!(lines[0], "[0] = 1002");
+        assert_eq!(
+            lines[MAX_PRINTED_CELLS],
+            "(showing the first 1000 of 1000000000000 cells)"
+        );
+        assert_eq!(
+            debugger.execute("set 18446744073709551615 1").unwrap(),
+            vec!["18446744073709551615 is beyond the machine's memory"]
+        );
+        assert_eq!(debugger.execute("quit"), None);
+    }
+}
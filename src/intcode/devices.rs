@@ -0,0 +1,272 @@
+//! Connecting machines to whatever supplies their input and takes their
+//! output.
+//!
+//! A machine only ever asks for input when it's blocked on an input
+//! instruction, and only ever produces output one value at a time, so `run`
+//! can sit outside the interpreter: it resumes the machine, and whenever it
+//! stops for input or output, talks to an `InputDevice` or an
+//! `OutputDevice`. Devices are provided for queues, closures, channels and
+//! text streams such as files and the standard streams; anything else, such
+//! as a robot or a screen, just needs to implement the traits.
+
+use super::{Machine, Status, VmError};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+/// Supplies a machine's input.
+pub trait InputDevice {
+    /// The next input value, or `None` if there won't be any more.
+    fn read(&mut self) -> io::Result<Option<i64>>;
+}
+
+/// Takes a machine's output.
+pub trait OutputDevice {
+    fn write(&mut self, value: i64) -> io::Result<()>;
+}
+
+/// A fixed queue of inputs.
+impl InputDevice for VecDeque<i64> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok(self.pop_front())
+    }
+}
+
+/// Collects output in order.
+impl OutputDevice for Vec<i64> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        self.push(value);
+        Ok(())
+    }
+}
+
+/// Input from a closure, which returns `None` once it has run out.
+pub struct InputFn<F: FnMut() -> Option<i64>>(pub F);
+
+impl<F: FnMut() -> Option<i64>> InputDevice for InputFn<F> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok((self.0)())
+    }
+}
+
+/// Output to a closure.
+pub struct OutputFn<F: FnMut(i64)>(pub F);
+
+impl<F: FnMut(i64)> OutputDevice for OutputFn<F> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        (self.0)(value);
+        Ok(())
+    }
+}
+
+/// Input from a channel, waiting for each value. The input ends when every
+/// sender has gone.
+impl InputDevice for Receiver<i64> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok(self.recv().ok())
+    }
+}
+
+/// Output to a channel. Writing fails once the receiver has gone.
+impl OutputDevice for Sender<i64> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        self.send(value)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output channel closed"))
+    }
+}
+
+/// Input from a text stream, such as a file or standard input, holding
+/// integers separated by commas, spaces or newlines. Lines are only read as
+/// the machine needs them, so input can be typed interactively.
+pub struct TextInput<R: BufRead> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> TextInput<R> {
+    pub fn new(reader: R) -> TextInput<R> {
+        TextInput {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> InputDevice for TextInput<R> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            for word in line.split(|c: char| c == ',' || c.is_whitespace()) {
+                if word.is_empty() {
+                    continue;
+                }
+
+                let value = word.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid input value {:?}", word),
+                    )
+                })?;
+                self.pending.push_back(value);
+            }
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Output to a text stream, such as a file or standard output, one value
+/// per line. Each value is flushed as soon as it's written.
+pub struct TextOutput<W: Write>(pub W);
+
+impl<W: Write> OutputDevice for TextOutput<W> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        writeln!(self.0, "{}", value)?;
+        self.0.flush()
+    }
+}
+
+/// A reason a machine connected to devices stopped early.
+#[derive(Debug)]
+pub enum DeviceError {
+    /// The machine failed, or wanted input after its device ran out.
+    Machine(VmError),
+    /// A device failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceError::Machine(error) => write!(f, "{}", error),
+            DeviceError::Io(error) => write!(f, "device failed: {}", error),
+        }
+    }
+}
+
+impl Error for DeviceError {}
+
+/// Runs `machine` until it halts, reading input from `input` whenever it
+/// needs some and writing each output value to `output` as it's produced.
+/// Output goes only to the device; it doesn't pile up in the machine.
+pub fn run(
+    machine: &mut Machine,
+    input: &mut impl InputDevice,
+    output: &mut impl OutputDevice,
+) -> Result<(), DeviceError> {
+    loop {
+        match machine.resume().map_err(DeviceError::Machine)? {
+            Status::Halted => return Ok(()),
+            Status::NeedsInput => match input.read().map_err(DeviceError::Io)? {
+                Some(value) => machine.push_input(value),
+                None => {
+                    return Err(DeviceError::Machine(VmError::InputExhausted {
+                        pc: machine.program_counter(),
+                        instruction: machine.read(machine.program_counter()),
+                    }))
+                }
+            },
+            Status::Output(value) => {
+                machine.take_output();
+                output.write(value).map_err(DeviceError::Io)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // Outputs double each input until it reads a zero
+    const DOUBLER: [i64; 16] = [
+        3, 15, 1006, 15, 14, 102, 2, 15, 15, 4, 15, 1105, 1, 0, 99, 0,
+    ];
+
+    fn doubler() -> Machine {
+        Machine::new(&DOUBLER)
+    }
+
+    #[test]
+    fn test_queue_and_vec() {
+        let mut input: VecDeque<i64> = vec![1, 2, 3, 0].into();
+        let mut output = Vec::new();
+
+        run(&mut doubler(), &mut input, &mut output).unwrap();
+        assert_eq!(output, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut next = 5;
+        let mut sum = 0;
+
+        let result = run(
+            &mut doubler(),
+            &mut InputFn(|| {
+                next -= 1;
+                Some(next)
+            }),
+            &mut OutputFn(|value| sum += value),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(sum, 2 * (4 + 3 + 2 + 1));
+    }
+
+    #[test]
+    fn test_channels() {
+        let (input_sender, input_receiver) = mpsc::channel();
+        let (output_sender, output_receiver) = mpsc::channel();
+
+        let worker = thread::spawn(move || {
+            let mut input_receiver = input_receiver;
+            let mut output_sender = output_sender;
+            run(&mut doubler(), &mut input_receiver, &mut output_sender).is_ok()
+        });
+
+        for value in 1..4 {
+            input_sender.send(value).unwrap();
+            assert_eq!(output_receiver.recv(), Ok(2 * value));
+        }
+
+        input_sender.send(0).unwrap();
+        assert!(worker.join().unwrap());
+    }
+
+    #[test]
+    fn test_text() {
+        let mut input = TextInput::new(io::Cursor::new("1, 2\n\n3 4\n"));
+        let mut output = TextOutput(Vec::new());
+
+        let result = run(&mut doubler(), &mut input, &mut output);
+        assert!(matches!(
+            result,
+            Err(DeviceError::Machine(VmError::InputExhausted { .. }))
+        ));
+        assert_eq!(String::from_utf8(output.0).unwrap(), "2\n4\n6\n8\n");
+
+        let mut input = TextInput::new(io::Cursor::new("1,x\n"));
+        assert_eq!(input.read().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_output_errors() {
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+
+        let mut input: VecDeque<i64> = vec![1].into();
+        let mut output = sender;
+        let result = run(&mut doubler(), &mut input, &mut output);
+        assert!(matches!(result, Err(DeviceError::Io(_))));
+    }
+}
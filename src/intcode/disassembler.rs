@@ -0,0 +1,340 @@
+//! Turns Intcode programs back into annotated listings.
+//!
+//! Intcode doesn't separate code from data, so the disassembler guesses: it
+//! follows control flow from address 0 and treats everything it can reach as
+//! code and everything else as data.
+
+use super::{decode_opcode, get_mode, opcode_info, ParameterMode};
+use std::collections::{BTreeSet, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A single instruction parameter as it appears in memory.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Operand {
+    pub mode: ParameterMode,
+    pub value: i64,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            ParameterMode::Position => write!(f, "[{}]", self.value),
+            ParameterMode::Immediate => write!(f, "#{}", self.value),
+            ParameterMode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            ParameterMode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+/// A decoded instruction.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Instruction {
+    pub address: usize,
+    pub opcode: usize,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    /// The raw words making up the instruction, opcode first.
+    pub words: Vec<i64>,
+}
+
+impl Instruction {
+    /// The number of words the instruction occupies. Never zero, since the
+    /// opcode is always there.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Where control may go after this instruction, as far as can be told
+    /// from `memory` without running it. Only immediate jump targets are
+    /// known for sure. One read through a position parameter could be
+    /// rewritten before the jump runs, so, like one read through a relative
+    /// parameter, the jump is marked dynamic.
+    pub fn control_flow(&self, memory: &[i64]) -> ControlFlow {
+        let fallthrough = Some(self.address + self.len());
+
+        match self.opcode {
+            5 | 6 => {
+                let test = self.operands[0];
+                let always_jumps = test.mode == ParameterMode::Immediate
+                    && (test.value != 0) == (self.opcode == 5);
+                let never_jumps = test.mode == ParameterMode::Immediate && !always_jumps;
+                let fallthrough = if always_jumps { None } else { fallthrough };
+
+                if never_jumps {
+                    return ControlFlow {
+                        fallthrough,
+                        ..ControlFlow::default()
+                    };
+                }
+
+                // Jumps outside memory halt or fail, so go nowhere
+                let in_memory = |target: i64| -> Option<usize> {
+                    if target >= 0 && (target as usize) < memory.len() {
+                        Some(target as usize)
+                    } else {
+                        None
+                    }
+                };
+                let target = self.operands[1];
+
+                match target.mode {
+                    ParameterMode::Immediate => ControlFlow {
+                        fallthrough,
+                        jump: in_memory(target.value),
+                        ..ControlFlow::default()
+                    },
+                    ParameterMode::Position => ControlFlow {
+                        fallthrough,
+                        dynamic: true,
+                        stored_jump: usize::try_from(target.value)
+                            .ok()
+                            .and_then(|address| memory.get(address))
+                            .and_then(|&target| in_memory(target)),
+                        ..ControlFlow::default()
+                    },
+                    ParameterMode::Relative => ControlFlow {
+                        fallthrough,
+                        dynamic: true,
+                        ..ControlFlow::default()
+                    },
+                }
+            }
+            99 => ControlFlow::default(),
+            _ => ControlFlow {
+                fallthrough,
+                ..ControlFlow::default()
+            },
+        }
+    }
+
+    /// The addresses control may pass to after this instruction, as worked
+    /// out by `control_flow`. Dynamic jump targets, including stored ones,
+    /// are left out.
+    pub fn successors(&self, memory: &[i64]) -> Vec<usize> {
+        let flow = self.control_flow(memory);
+        flow.jump.into_iter().chain(flow.fallthrough).collect()
+    }
+}
+
+/// Where control may go after an instruction.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ControlFlow {
+    /// The next instruction in memory, unless the instruction always jumps
+    /// or halts.
+    pub fallthrough: Option<usize>,
+    /// Where the instruction may jump, if that can be told without running
+    /// it.
+    pub jump: Option<usize>,
+    /// Whether the instruction may jump somewhere that can only be found by
+    /// running it.
+    pub dynamic: bool,
+    /// For a jump through a position parameter, where memory says it goes
+    /// before the program has had a chance to change it. Good enough for
+    /// finding code to list, but not to be relied on.
+    pub stored_jump: Option<usize>,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+
+        for (index, operand) in self.operands.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, operand)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at `address`, or returns `None` if the words there
+/// can't be a valid instruction.
+pub fn decode_instruction(memory: &[i64], address: usize) -> Option<Instruction> {
+    let raw = *memory.get(address)?;
+
+    if raw < 0 {
+        return None;
+    }
+
+    let (opcode, modes) = decode_opcode(raw as usize).ok()?;
+    let info = opcode_info(opcode)?;
+
+    if modes.len() > info.parameter_count {
+        return None;
+    }
+
+    let words = memory.get(address..address + 1 + info.parameter_count)?;
+    let operands: Vec<Operand> = words[1..]
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| Operand {
+            mode: get_mode(&modes, index),
+            value,
+        })
+        .collect();
+
+    if let Some(write_parameter) = info.write_parameter {
+        if operands[write_parameter].mode == ParameterMode::Immediate {
+            return None;
+        }
+    }
+
+    Some(Instruction {
+        address,
+        opcode,
+        mnemonic: info.mnemonic,
+        operands,
+        words: words.to_vec(),
+    })
+}
+
+/// Finds the addresses of every instruction reachable from address 0.
+pub fn find_instructions(memory: &[i64]) -> BTreeSet<usize> {
+    let mut instructions = BTreeSet::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![0];
+
+    while let Some(address) = pending.pop() {
+        if !visited.insert(address) {
+            continue;
+        }
+
+        if let Some(instruction) = decode_instruction(memory, address) {
+            let flow = instruction.control_flow(memory);
+            pending.extend(flow.jump.into_iter().chain(flow.stored_jump));
+            pending.extend(flow.fallthrough);
+            instructions.insert(address);
+        }
+    }
+
+    instructions
+}
+
+/// One line of a listing.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Line {
+    Code(Instruction),
+    Data { address: usize, value: i64 },
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Code(instruction) => {
+                let words: Vec<String> = instruction.words.iter().map(|w| w.to_string()).collect();
+                write!(
+                    f,
+                    "{:>6}  {:<24}  {}",
+                    instruction.address,
+                    words.join(","),
+                    instruction
+                )
+            }
+            Line::Data { address, value } => {
+                write!(f, "{:>6}  {:<24}  DATA {}", address, value, value)
+            }
+        }
+    }
+}
+
+/// Disassembles a whole program.
+pub fn disassemble(memory: &[i64]) -> Vec<Line> {
+    let instructions = find_instructions(memory);
+    let mut lines = Vec::new();
+    let mut address = 0;
+
+    while address < memory.len() {
+        let instruction = if instructions.contains(&address) {
+            decode_instruction(memory, address)
+        } else {
+            None
+        };
+
+        match instruction {
+            Some(instruction) => {
+                address += instruction.len();
+                lines.push(Line::Code(instruction));
+            }
+            None => {
+                lines.push(Line::Data {
+                    address,
+                    value: memory[address],
+                });
+                address += 1;
+            }
+        }
+    }
+
+    lines
+}
+
+/// Renders a program as a listing, one line per instruction or data word.
+pub fn listing(memory: &[i64]) -> String {
+    disassemble(memory)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operand_display() {
+        let operand = |mode, value| Operand { mode, value }.to_string();
+
+        assert_eq!(operand(ParameterMode::Position, 12), "[12]");
+        assert_eq!(operand(ParameterMode::Immediate, 5), "#5");
+        assert_eq!(operand(ParameterMode::Immediate, -5), "#-5");
+        assert_eq!(operand(ParameterMode::Relative, 3), "rb+3");
+        assert_eq!(operand(ParameterMode::Relative, -1), "rb-1");
+    }
+
+    #[test]
+    fn test_decode_instruction() {
+        let memory = [1002, 4, 3, 4, 33];
+        let instruction = decode_instruction(&memory, 0).unwrap();
+        assert_eq!(instruction.to_string(), "MUL [4], #3, [4]");
+        assert_eq!(instruction.len(), 4);
+
+        // Unknown opcode, immediate write, truncated instruction
+        assert_eq!(decode_instruction(&memory, 4), None);
+        assert_eq!(decode_instruction(&[11101, 1, 1, 0], 0), None);
+        assert_eq!(decode_instruction(&[1, 0, 0], 0), None);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // Outputs 1 if the input equals 8, with the constant stored as data
+        let memory = [3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        let lines: Vec<String> = disassemble(&memory).iter().map(|l| l.to_string()).collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "     0  3,9                       IN [9]",
+                "     2  8,9,10,9                  EQ [9], [10], [9]",
+                "     6  4,9                       OUT [9]",
+                "     8  99                        HLT",
+                "     9  -1                        DATA -1",
+                "    10  8                         DATA 8",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_follows_jumps() {
+        // Jumps over a data word that would otherwise decode as an instruction
+        let memory = [1105, 1, 4, 1, 204, -1, 99];
+        let instructions = find_instructions(&memory);
+        assert_eq!(instructions.into_iter().collect::<Vec<_>>(), vec![0, 4, 6]);
+
+        let listing = listing(&memory);
+        assert!(listing.contains("JNZ #1, #4"));
+        assert!(listing.contains("DATA 1"));
+        assert!(listing.contains("OUT rb-1"));
+    }
+}
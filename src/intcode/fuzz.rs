@@ -0,0 +1,565 @@
+//! Fuzzing the interpreter with random and mutated programs.
+//!
+//! A `Fuzzer` generates programs, either from scratch or by mutating seed
+//! programs such as day 5's samples, and runs each one with a step budget,
+//! cycle detection and a small memory limit. Every run that fails is sorted into a
+//! `FailureClass` by the error it failed with. For each class, the shortest
+//! failing program found is then shrunk to a minimal reproducer: words are
+//! removed and simplified for as long as the program still fails the same
+//! way.
+//!
+//! Reproducers can be saved as fixtures, which the tests replay to check that
+//! each still fails the way it did:
+//!
+//! ```text
+//! class unknown-opcode
+//! inputs 8
+//! program 98
+//! ```
+//!
+//! Generation is driven by a seeded pseudo-random generator, so a fuzzing
+//! session can be repeated exactly.
+
+use super::{Machine, VmError};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic;
+
+/// The instructions a fuzzed program may execute before it's considered
+/// stuck.
+pub const DEFAULT_STEP_LIMIT: u64 = 10_000;
+
+/// The memory fuzzed programs may address, kept small so that stray writes
+/// fail quickly instead of allocating huge memories.
+pub const MEMORY_LIMIT: usize = 1 << 12;
+
+/// Day 5's samples, which between them use every instruction but the
+/// relative base adjustment.
+pub const DAY_5_SAMPLES: [&[i64]; 7] = [
+    &[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8],
+    &[3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8],
+    &[3, 3, 1108, -1, 8, 3, 4, 3, 99],
+    &[3, 3, 1107, -1, 8, 3, 4, 3, 99],
+    &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+    &[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+    &[
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99,
+    ],
+];
+
+const OPCODES: [i64; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+const INTERESTING: [i64; 9] = [0, 1, -1, 2, 8, 99, 1 << 12, i64::MAX, i64::MIN];
+
+/// How a program failed.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum FailureClass {
+    UnknownOpcode,
+    InvalidMode,
+    ImmediateWrite,
+    OutOfBoundsRead,
+    OutOfBoundsWrite,
+    NegativeAddress,
+    InputExhausted,
+    StepLimitExceeded,
+    InfiniteLoop,
+    Overflow,
+    /// The interpreter panicked, which is always a bug.
+    Panic,
+}
+
+impl FailureClass {
+    pub const ALL: [FailureClass; 11] = [
+        FailureClass::UnknownOpcode,
+        FailureClass::InvalidMode,
+        FailureClass::ImmediateWrite,
+        FailureClass::OutOfBoundsRead,
+        FailureClass::OutOfBoundsWrite,
+        FailureClass::NegativeAddress,
+        FailureClass::InputExhausted,
+        FailureClass::StepLimitExceeded,
+        FailureClass::InfiniteLoop,
+        FailureClass::Overflow,
+        FailureClass::Panic,
+    ];
+
+    pub fn of(error: &VmError) -> FailureClass {
+        match error {
+            VmError::UnknownOpcode { .. } => FailureClass::UnknownOpcode,
+            VmError::InvalidMode { .. } => FailureClass::InvalidMode,
+            VmError::ImmediateWrite { .. } => FailureClass::ImmediateWrite,
+            VmError::OutOfBoundsRead { .. } => FailureClass::OutOfBoundsRead,
+            VmError::OutOfBoundsWrite { .. } => FailureClass::OutOfBoundsWrite,
+            VmError::NegativeAddress { .. } => FailureClass::NegativeAddress,
+            VmError::InputExhausted { .. } => FailureClass::InputExhausted,
+            VmError::StepLimitExceeded { .. } => FailureClass::StepLimitExceeded,
+            VmError::InfiniteLoop { .. } => FailureClass::InfiniteLoop,
+            VmError::Overflow { .. } => FailureClass::Overflow,
+        }
+    }
+
+    /// The class's name in fixtures and file names.
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureClass::UnknownOpcode => "unknown-opcode",
+            FailureClass::InvalidMode => "invalid-mode",
+            FailureClass::ImmediateWrite => "immediate-write",
+            FailureClass::OutOfBoundsRead => "out-of-bounds-read",
+            FailureClass::OutOfBoundsWrite => "out-of-bounds-write",
+            FailureClass::NegativeAddress => "negative-address",
+            FailureClass::InputExhausted => "input-exhausted",
+            FailureClass::StepLimitExceeded => "step-limit-exceeded",
+            FailureClass::InfiniteLoop => "infinite-loop",
+            FailureClass::Overflow => "overflow",
+            FailureClass::Panic => "panic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FailureClass> {
+        FailureClass::ALL
+            .iter()
+            .copied()
+            .find(|class| class.name() == name)
+    }
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Runs `program` the way the fuzzer does, returning how it failed, if it
+/// did.
+pub fn execute(program: &[i64], inputs: &[i64]) -> Option<FailureClass> {
+    let result = panic::catch_unwind(|| {
+        let mut machine = Machine::new(program);
+        machine.set_step_limit(Some(DEFAULT_STEP_LIMIT));
+        machine.set_memory_limit(MEMORY_LIMIT);
+        machine.set_cycle_detection(true);
+
+        for &input in inputs {
+            machine.push_input(input);
+        }
+
+        machine.run()
+    });
+
+    match result {
+        Ok(Ok(())) => None,
+        Ok(Err(error)) => Some(FailureClass::of(&error)),
+        Err(_) => Some(FailureClass::Panic),
+    }
+}
+
+/// Shrinks `program` while `still_fails` holds: first by removing runs of
+/// words, longest first, then by replacing words with simpler values.
+pub fn minimize(program: &[i64], still_fails: impl Fn(&[i64]) -> bool) -> Vec<i64> {
+    let mut program = program.to_vec();
+
+    loop {
+        let before = program.clone();
+        let mut chunk = program.len() / 2;
+
+        while chunk > 0 {
+            let mut start = 0;
+
+            while start < program.len() {
+                let mut candidate = program.clone();
+                candidate.drain(start..(start + chunk).min(program.len()));
+
+                if still_fails(&candidate) {
+                    program = candidate;
+                } else {
+                    start += chunk;
+                }
+            }
+
+            chunk /= 2;
+        }
+
+        for index in 0..program.len() {
+            let value = program[index];
+
+            for &simpler in &[0, 1, value / 2] {
+                if simpler.unsigned_abs() >= value.unsigned_abs() {
+                    continue;
+                }
+
+                program[index] = simpler;
+
+                if still_fails(&program) {
+                    break;
+                }
+
+                program[index] = value;
+            }
+        }
+
+        if program == before {
+            return program;
+        }
+    }
+}
+
+/// A failing program saved for replay.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Fixture {
+    pub class: FailureClass,
+    pub inputs: Vec<i64>,
+    pub program: Vec<i64>,
+}
+
+fn join(values: &[i64]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    values.join(",")
+}
+
+fn split(values: &str) -> Option<Vec<i64>> {
+    if values.is_empty() {
+        return Some(Vec::new());
+    }
+
+    values.split(',').map(|v| v.trim().parse().ok()).collect()
+}
+
+impl Fixture {
+    /// A file name for the fixture that's the same for identical fixtures.
+    pub fn file_name(&self) -> String {
+        // FNV-1a, which unlike the standard hasher is stable across releases
+        let hash = self
+            .to_text()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+
+        format!("{}-{:016x}.txt", self.class, hash)
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "class {}\ninputs {}\nprogram {}\n",
+            self.class,
+            join(&self.inputs),
+            join(&self.program)
+        )
+    }
+
+    /// Reads a fixture written by `to_text`, or returns `None` if it's
+    /// malformed.
+    pub fn parse(text: &str) -> Option<Fixture> {
+        let mut class = None;
+        let mut inputs = None;
+        let mut program = None;
+
+        for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+
+            match field {
+                "class" => class = Some(FailureClass::from_name(value)?),
+                "inputs" => inputs = Some(split(value)?),
+                "program" => program = Some(split(value)?),
+                _ => return None,
+            }
+        }
+
+        Some(Fixture {
+            class: class?,
+            inputs: inputs?,
+            program: program?,
+        })
+    }
+
+    /// Whether the program still fails the way it did when it was saved.
+    pub fn reproduces(&self) -> bool {
+        execute(&self.program, &self.inputs) == Some(self.class)
+    }
+}
+
+/// What the fuzzer found for one failure class.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Crash {
+    /// The number of programs that failed this way.
+    pub count: u64,
+    /// The shortest of them, before minimization.
+    pub original: Vec<i64>,
+    /// A minimal program that fails the same way.
+    pub minimized: Vec<i64>,
+}
+
+/// The results of a fuzzing session.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Report {
+    pub runs: u64,
+    pub crashes: BTreeMap<FailureClass, Crash>,
+}
+
+/// A small xorshift generator; fuzzing doesn't need anything better.
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Xorshift gets stuck at zero, and small seeds start off poorly
+        Rng((seed ^ 0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Generates programs and collects the ways they fail.
+pub struct Fuzzer {
+    rng: Rng,
+    seeds: Vec<Vec<i64>>,
+    inputs: Vec<i64>,
+}
+
+impl Fuzzer {
+    /// A fuzzer that mutates `seeds`, driven by the random seed `seed`.
+    /// Without seeds, every program is generated from scratch.
+    pub fn new(seed: u64, seeds: &[Vec<i64>]) -> Fuzzer {
+        Fuzzer {
+            rng: Rng::new(seed),
+            seeds: seeds.to_vec(),
+            inputs: vec![8; 4],
+        }
+    }
+
+    /// Sets the inputs every program is run with.
+    pub fn set_inputs(&mut self, inputs: &[i64]) {
+        self.inputs = inputs.to_vec();
+    }
+
+    fn random_instruction(&mut self) -> i64 {
+        let opcode = if self.rng.below(16) == 0 {
+            self.rng.below(100) as i64
+        } else {
+            *self.rng.choose(&OPCODES)
+        };
+
+        let modes_bound = if self.rng.below(16) == 0 { 10 } else { 3 };
+        let modes = (0..3).fold(0, |modes, _| {
+            modes * 10 + self.rng.below(modes_bound) as i64
+        });
+
+        modes * 100 + opcode
+    }
+
+    fn random_word(&mut self, len: usize) -> i64 {
+        match self.rng.below(4) {
+            0 => self.random_instruction(),
+            1 => *self.rng.choose(&INTERESTING),
+            _ => self.rng.below(len + 4) as i64 - 2,
+        }
+    }
+
+    fn random_program(&mut self) -> Vec<i64> {
+        let len = 1 + self.rng.below(32);
+        (0..len).map(|_| self.random_word(len)).collect()
+    }
+
+    fn mutate(&mut self, program: &mut Vec<i64>) {
+        let len = program.len();
+
+        if len == 0 {
+            program.push(self.random_word(1));
+            return;
+        }
+
+        let index = self.rng.below(len);
+
+        match self.rng.below(6) {
+            0 => program[index] = self.random_word(len),
+            1 => program.insert(index, self.random_word(len)),
+            2 => {
+                program.remove(index);
+            }
+            3 => {
+                program[index] =
+                    program[index].wrapping_add(if self.rng.below(2) == 0 { 1 } else { -1 })
+            }
+            // Keep the opcode but pick new modes
+            4 => program[index] = self.random_instruction() / 100 * 100 + program[index] % 100,
+            _ => {
+                let other = self.rng.choose(&self.seeds);
+                let start = self.rng.below(other.len() + 1);
+                program.truncate(index);
+                program.extend_from_slice(&other[start..]);
+            }
+        }
+    }
+
+    /// Makes the next program to try.
+    pub fn generate(&mut self) -> Vec<i64> {
+        if self.seeds.is_empty() || self.rng.below(4) == 0 {
+            return self.random_program();
+        }
+
+        let mut program = self.rng.choose(&self.seeds).clone();
+
+        for _ in 0..1 + self.rng.below(4) {
+            self.mutate(&mut program);
+        }
+
+        program
+    }
+
+    /// Runs `runs` generated programs, then minimizes the shortest program
+    /// found for each way of failing.
+    pub fn run(&mut self, runs: u64) -> Report {
+        let mut shortest: BTreeMap<FailureClass, (u64, Vec<i64>)> = BTreeMap::new();
+
+        for _ in 0..runs {
+            let program = self.generate();
+
+            if let Some(class) = execute(&program, &self.inputs) {
+                let entry = shortest.entry(class).or_insert((0, program.clone()));
+                entry.0 += 1;
+
+                if program.len() < entry.1.len() {
+                    entry.1 = program;
+                }
+            }
+        }
+
+        let inputs = &self.inputs;
+        let crashes = shortest
+            .into_iter()
+            .map(|(class, (count, original))| {
+                let minimized =
+                    minimize(&original, |program| execute(program, inputs) == Some(class));
+
+                let crash = Crash {
+                    count,
+                    original,
+                    minimized,
+                };
+                (class, crash)
+            })
+            .collect();
+
+        Report { runs, crashes }
+    }
+
+    /// The fixture for a crash found by this fuzzer.
+    pub fn fixture(&self, class: FailureClass, crash: &Crash) -> Fixture {
+        Fixture {
+            class,
+            inputs: self.inputs.clone(),
+            program: crash.minimized.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_execute() {
+        assert_eq!(execute(DAY_5_SAMPLES[0], &[8]), None);
+        assert_eq!(execute(&[98], &[]), Some(FailureClass::UnknownOpcode));
+        assert_eq!(
+            execute(&[3, 0, 99], &[]),
+            Some(FailureClass::InputExhausted)
+        );
+        assert_eq!(
+            execute(&[1105, 1, 0], &[]),
+            Some(FailureClass::InfiniteLoop)
+        );
+        assert_eq!(
+            execute(&[1001, 5, 1, 5, 1105, 1, 0], &[]),
+            Some(FailureClass::StepLimitExceeded)
+        );
+        assert_eq!(
+            execute(&[4, 4096], &[]),
+            Some(FailureClass::OutOfBoundsRead)
+        );
+    }
+
+    #[test]
+    fn test_minimize() {
+        // Only the unknown opcode at the end matters
+        let program = [1101, 2, 3, 7, 104, 5, 1002, 7, 3, 7, 42];
+        let minimized = minimize(&program, |p| {
+            execute(p, &[]) == Some(FailureClass::UnknownOpcode)
+        });
+        assert_eq!(minimized, vec![0]);
+
+        let program = [4, 3, 99, 5000];
+        let minimized = minimize(&program, |p| {
+            execute(p, &[]) == Some(FailureClass::OutOfBoundsRead)
+        });
+        assert_eq!(
+            execute(&minimized, &[]),
+            Some(FailureClass::OutOfBoundsRead)
+        );
+        assert!(minimized.len() <= 2);
+    }
+
+    #[test]
+    fn test_fuzzing_is_repeatable() {
+        let seeds: Vec<Vec<i64>> = DAY_5_SAMPLES.iter().map(|s| s.to_vec()).collect();
+        let report = Fuzzer::new(1, &seeds).run(500);
+
+        assert_eq!(report, Fuzzer::new(1, &seeds).run(500));
+        assert!(report.crashes.contains_key(&FailureClass::UnknownOpcode));
+        assert!(!report.crashes.contains_key(&FailureClass::Panic));
+
+        for (&class, crash) in &report.crashes {
+            assert!(crash.minimized.len() <= crash.original.len());
+            assert_eq!(execute(&crash.minimized, &[8; 4]), Some(class));
+        }
+    }
+
+    #[test]
+    fn test_fixture_text() {
+        let fixture = Fixture {
+            class: FailureClass::NegativeAddress,
+            inputs: vec![],
+            program: vec![4, -1],
+        };
+
+        assert_eq!(
+            fixture.to_text(),
+            "class negative-address\ninputs \nprogram 4,-1\n"
+        );
+        assert_eq!(Fixture::parse(&fixture.to_text()), Some(fixture));
+        assert_eq!(Fixture::parse("class nonsense\ninputs\nprogram 1"), None);
+    }
+
+    #[test]
+    fn test_regression_fixtures() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/fuzz");
+
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            let fixture = Fixture::parse(&fs::read_to_string(&path).unwrap())
+                .unwrap_or_else(|| panic!("malformed fixture {}", path.display()));
+
+            assert!(
+                fixture.reproduces(),
+                "{} no longer reproduces",
+                path.display()
+            );
+        }
+    }
+}
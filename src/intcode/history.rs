@@ -0,0 +1,253 @@
+//! Execution history for stepping a machine backwards.
+//!
+//! A `History` is a tracer that keeps an undo record for every instruction a
+//! machine executes: where it was, which memory cell it overwrote and what
+//! used to be there, and what input it consumed. Replaying those records in
+//! reverse rewinds the machine to any earlier instruction without rerunning
+//! the program. Memory patched by hand between instructions is recorded too,
+//! so rewinding past a patch takes it back out.
+//!
+//! Only the most recent instructions are kept, up to the history's capacity;
+//! older ones are forgotten, and the machine can't be rewound past them.
+
+use super::{Machine, MemoryWrite, TraceEvent, Tracer};
+use std::collections::VecDeque;
+
+/// How many instructions a history keeps unless configured otherwise.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// The undo records for the most recent part of a machine's run, oldest
+/// first.
+#[derive(Clone, Debug)]
+pub struct History {
+    events: VecDeque<TraceEvent>,
+    /// Patches, each with the number of recorded instructions executed
+    /// before it.
+    patches: Vec<(usize, MemoryWrite)>,
+    capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Creates a history that keeps at most `capacity` instructions.
+    pub fn with_capacity(capacity: usize) -> History {
+        History {
+            events: VecDeque::new(),
+            patches: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// The most instructions the history keeps.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of instructions that can be undone.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Overwrites a memory cell between instructions, recording the old
+    /// value so that rewinding past the patch restores it.
+    pub fn patch(&mut self, machine: &mut Machine, address: usize, value: i64) {
+        let write = MemoryWrite {
+            address,
+            old_value: machine.read(address),
+            new_value: value,
+        };
+
+        self.patches.push((self.events.len(), write));
+        machine.write(address, value);
+    }
+
+    /// Undoes the most recent instruction, along with any patches made since,
+    /// returning the instruction. `machine` must be the machine this history
+    /// was recorded from.
+    pub fn step_back(&mut self, machine: &mut Machine) -> Option<TraceEvent> {
+        while let Some(&(position, write)) = self.patches.last() {
+            if position < self.events.len() {
+                break;
+            }
+
+            machine.write(write.address, write.old_value);
+            self.patches.pop();
+        }
+
+        let event = self.events.pop_back()?;
+        machine.undo(&event);
+        Some(event)
+    }
+
+    /// Undoes instructions until only the first `len` remain.
+    pub fn rewind_to(&mut self, machine: &mut Machine, len: usize) {
+        while self.events.len() > len {
+            self.step_back(machine);
+        }
+    }
+
+    /// Finds the most recent instruction that wrote to `address`, along with
+    /// its position in the history.
+    pub fn last_write(&self, address: usize) -> Option<(usize, &TraceEvent)> {
+        self.events
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, event)| event.write.is_some_and(|w| w.address == address))
+    }
+}
+
+impl Tracer for History {
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+
+            // Patches made before the forgotten instruction can't be reached
+            // any more, and the rest move down a place
+            self.patches.retain(|&(position, _)| position > 0);
+            for (position, _) in &mut self.patches {
+                *position -= 1;
+            }
+        }
+
+        self.events.push_back(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewind_restores_state() {
+        let program = [109, 5, 203, 10, 1002, 15, 3, 15, 4, 15, 99];
+        let mut machine = Machine::new(&program);
+        let mut history = History::new();
+        let initial = machine.clone();
+
+        machine.push_input(7);
+        machine.run_traced(&mut history).unwrap();
+        assert_eq!(machine.output(), &[21]);
+        assert_eq!(history.len(), 5);
+
+        history.rewind_to(&mut machine, 2);
+        assert_eq!(machine.program_counter(), 4);
+        assert_eq!(machine.read(15), 7);
+        assert!(!machine.is_halted());
+
+        history.rewind_to(&mut machine, 0);
+        assert!(history.is_empty());
+        assert_eq!(machine.program_counter(), initial.program_counter());
+        assert_eq!(machine.relative_base(), 0);
+        assert_eq!(machine.steps(), 0);
+        assert_eq!(&machine.memory()[..program.len()], &program[..]);
+        assert_eq!(machine.read(15), 0);
+
+        // The consumed input is back in the queue and the output taken back,
+        // so the run replays exactly
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[21]);
+    }
+
+    #[test]
+    fn test_last_write() {
+        // Writes 1, then 2, then 3 to address 13
+        let program = [1101, 0, 1, 13, 1101, 0, 2, 13, 1101, 0, 3, 13, 99, 0];
+        let mut machine = Machine::new(&program);
+        let mut history = History::new();
+        machine.run_traced(&mut history).unwrap();
+
+        let (index, event) = history.last_write(13).unwrap();
+        assert_eq!((index, event.pc), (2, 8));
+        assert_eq!(history.last_write(12), None);
+
+        history.step_back(&mut machine);
+        history.step_back(&mut machine);
+        assert_eq!(machine.read(13), 2);
+        assert_eq!(history.last_write(13).unwrap().1.pc, 4);
+    }
+
+    #[test]
+    fn test_patches() {
+        let program = [1101, 2, 3, 5, 99, 0];
+        let mut machine = Machine::new(&program);
+        let mut history = History::new();
+
+        history.patch(&mut machine, 1, 20);
+        machine.step_traced(&mut history).unwrap();
+        history.patch(&mut machine, 5, 42);
+        history.patch(&mut machine, 5, 43);
+        assert_eq!(machine.read(5), 43);
+
+        history.step_back(&mut machine);
+        assert_eq!(machine.read(5), 0);
+        assert_eq!(machine.read(1), 20);
+
+        // Patches made before the first instruction go too
+        assert_eq!(history.step_back(&mut machine), None);
+        assert_eq!(&machine.memory()[..program.len()], &program[..]);
+    }
+
+    #[test]
+    fn test_undo_output() {
+        let program = [104, 1, 104, 2, 99];
+        let mut machine = Machine::new(&program);
+        let mut history = History::new();
+        machine.set_cycle_detection(true);
+        machine.run_traced(&mut history).unwrap();
+        assert_eq!(machine.output(), &[1, 2]);
+
+        history.step_back(&mut machine);
+        history.step_back(&mut machine);
+        assert_eq!(machine.output(), &[1]);
+        assert_eq!(machine.steps(), 1);
+
+        // Output that's already been taken isn't touched
+        assert_eq!(machine.take_output(), vec![1]);
+        history.step_back(&mut machine);
+        assert_eq!(machine.output(), &[] as &[i64]);
+        assert_eq!(machine.steps(), 0);
+
+        machine.run().unwrap();
+        assert_eq!(machine.output(), &[1, 2]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        // Counts [12] up from 0 to 3, three instructions a time round
+        let program = [1001, 12, 1, 12, 1008, 12, 3, 13, 1006, 13, 0, 99, 0, 0, 0];
+        let mut machine = Machine::new(&program);
+        let mut history = History::with_capacity(4);
+        history.patch(&mut machine, 14, 99);
+        machine.step_traced(&mut history).unwrap();
+        history.patch(&mut machine, 14, 98);
+        machine.run_traced(&mut history).unwrap();
+
+        assert_eq!(history.len(), 4);
+        assert_eq!(machine.read(12), 3);
+
+        // Only the last time round and the halt can be undone; both patches
+        // were forgotten along with the instructions before them
+        while history.step_back(&mut machine).is_some() {}
+        assert_eq!(machine.program_counter(), 0);
+        assert_eq!(machine.read(12), 2);
+        assert_eq!(machine.read(14), 98);
+    }
+}
@@ -0,0 +1,496 @@
+//! A network of Intcode machines exchanging packets.
+//!
+//! Every node boots with its network address as its first input. A node
+//! sends a packet by outputting three values: the destination address, then
+//! X, then Y. Packets are queued for their destination, and a node that asks
+//! for input while its queue is empty receives -1 instead of blocking.
+//!
+//! Packets sent to the NAT address (255 by default) aren't delivered to a
+//! node. The NAT keeps the most recent one, and once the whole network has
+//! gone idle it sends that packet to node 0 to get things moving again.
+//!
+//! Scheduling is deterministic: each round, every node in address order
+//! receives one packet (or -1) and runs until it asks for input again. A
+//! node that runs more than its budget of instructions in a round without
+//! asking stops the network, so a node that never polls can't hang it.
+
+use super::{Machine, Status, VmError};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+/// The address packets are sent to for the NAT unless configured otherwise.
+pub const DEFAULT_NAT_ADDRESS: i64 = 255;
+
+/// How many instructions a node may run in a round, unless configured
+/// otherwise, before it's taken to be stuck.
+pub const DEFAULT_ROUND_BUDGET: u64 = 100_000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Packet {
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// Something noteworthy that happened during a round.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NetworkEvent {
+    /// A node sent a packet, either to another node or to the NAT.
+    Sent { from: usize, packet: Packet },
+    /// The network was idle, so the NAT sent its last packet to node 0.
+    NatDelivered(Packet),
+}
+
+/// A reason the network stopped.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NetworkError {
+    /// A node's machine failed.
+    Machine { address: usize, error: VmError },
+    /// A node sent a packet to an address with nothing there.
+    UnknownDestination { from: usize, packet: Packet },
+    /// The network went idle before the NAT had received anything to send.
+    Stalled,
+    /// A node ran for its whole budget in a round without asking for input.
+    Busy { address: usize, budget: u64 },
+    /// The network went idle, but node 0, which the NAT wakes it through,
+    /// has halted or doesn't exist.
+    NatTargetHalted,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Machine { address, error } => write!(f, "node {}: {}", address, error),
+            NetworkError::UnknownDestination { from, packet } => write!(
+                f,
+                "node {} sent a packet to unknown address {}",
+                from, packet.destination
+            ),
+            NetworkError::Stalled => write!(f, "the network is idle and the NAT has nothing"),
+            NetworkError::Busy { address, budget } => write!(
+                f,
+                "node {} ran {} instructions without asking for input",
+                address, budget
+            ),
+            NetworkError::NatTargetHalted => {
+                write!(f, "the network is idle and node 0 has halted")
+            }
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+#[derive(Clone, Debug)]
+struct Node {
+    machine: Machine,
+    queue: VecDeque<(i64, i64)>,
+    /// Values output towards a packet that isn't complete yet.
+    partial_packet: Vec<i64>,
+    /// How many times in a row the node has polled an empty queue without
+    /// sending anything.
+    idle_polls: usize,
+    /// Whether the node stopped last time because it asked for input.
+    waiting: bool,
+}
+
+/// A network of nodes all running the same program.
+#[derive(Clone, Debug)]
+pub struct Network {
+    nodes: Vec<Node>,
+    nat_address: i64,
+    nat_packet: Option<Packet>,
+    idle_threshold: usize,
+    round_budget: u64,
+}
+
+impl Network {
+    /// Boots `size` nodes, addressed from 0.
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let nodes = (0..size)
+            .map(|address| {
+                let mut machine = Machine::new(program);
+                machine.push_input(address as i64);
+
+                Node {
+                    machine,
+                    queue: VecDeque::new(),
+                    partial_packet: Vec::new(),
+                    idle_polls: 0,
+                    waiting: false,
+                }
+            })
+            .collect();
+
+        Network {
+            nodes,
+            nat_address: DEFAULT_NAT_ADDRESS,
+            nat_packet: None,
+            idle_threshold: 2,
+            round_budget: DEFAULT_ROUND_BUDGET,
+        }
+    }
+
+    /// Sets the address packets for the NAT are sent to.
+    pub fn set_nat_address(&mut self, address: i64) {
+        self.nat_address = address;
+    }
+
+    /// Sets how many empty polls in a row every node must make before the
+    /// network counts as idle.
+    pub fn set_idle_threshold(&mut self, polls: usize) {
+        self.idle_threshold = polls;
+    }
+
+    /// Sets how many instructions a node may run in a round.
+    pub fn set_round_budget(&mut self, instructions: u64) {
+        self.round_budget = instructions;
+    }
+
+    /// The packet the NAT will send next time the network is idle.
+    pub fn nat_packet(&self) -> Option<Packet> {
+        self.nat_packet
+    }
+
+    fn is_idle(&self) -> bool {
+        self.nodes.iter().all(|node| {
+            node.machine.is_halted()
+                || (node.queue.is_empty() && node.idle_polls >= self.idle_threshold)
+        })
+    }
+
+    fn route(&mut self, from: usize, packet: Packet) -> Result<(), NetworkError> {
+        if packet.destination == self.nat_address {
+            self.nat_packet = Some(packet);
+            return Ok(());
+        }
+
+        let node = match packet.destination {
+            destination if destination >= 0 => self.nodes.get_mut(destination as usize),
+            _ => None,
+        }
+        .ok_or(NetworkError::UnknownDestination { from, packet })?;
+
+        node.queue.push_back((packet.x, packet.y));
+        Ok(())
+    }
+
+    /// Runs one node until it asks for input again, routing the packets it
+    /// sends.
+    fn run_node(
+        &mut self,
+        address: usize,
+        events: &mut Vec<NetworkEvent>,
+    ) -> Result<(), NetworkError> {
+        let mut sent = Vec::new();
+        let budget = self.round_budget;
+        let node = &mut self.nodes[address];
+        let mut steps = 0;
+
+        loop {
+            if steps == budget {
+                return Err(NetworkError::Busy { address, budget });
+            }
+
+            steps += 1;
+
+            match node.machine.step() {
+                Ok(None) => {}
+                Ok(Some(Status::Output(value))) => {
+                    node.partial_packet.push(value);
+
+                    if let [destination, x, y] = node.partial_packet[..] {
+                        sent.push(Packet { destination, x, y });
+                        node.partial_packet.clear();
+                    }
+                }
+                Ok(Some(status)) => {
+                    node.waiting = status == Status::NeedsInput;
+                    break;
+                }
+                Err(error) => return Err(NetworkError::Machine { address, error }),
+            }
+        }
+
+        if !sent.is_empty() {
+            node.idle_polls = 0;
+        }
+
+        for packet in sent {
+            self.route(address, packet)?;
+            events.push(NetworkEvent::Sent {
+                from: address,
+                packet,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Runs a single round, returning what happened during it.
+    pub fn tick(&mut self) -> Result<Vec<NetworkEvent>, NetworkError> {
+        let mut events = Vec::new();
+
+        for address in 0..self.nodes.len() {
+            let node = &mut self.nodes[address];
+
+            if node.machine.is_halted() {
+                continue;
+            }
+
+            // Nodes that haven't asked for input yet (such as freshly booted
+            // ones still holding their address) are simply run.
+            if node.waiting {
+                match node.queue.pop_front() {
+                    Some((x, y)) => {
+                        node.machine.push_input(x);
+                        node.machine.push_input(y);
+                        node.idle_polls = 0;
+                    }
+                    None => {
+                        node.machine.push_input(-1);
+                        node.idle_polls += 1;
+                    }
+                }
+            }
+
+            self.run_node(address, &mut events)?;
+        }
+
+        if self.is_idle() {
+            if self
+                .nodes
+                .first()
+                .is_none_or(|node| node.machine.is_halted())
+            {
+                return Err(NetworkError::NatTargetHalted);
+            }
+
+            let packet = self.nat_packet.ok_or(NetworkError::Stalled)?;
+            self.nodes[0].queue.push_back((packet.x, packet.y));
+
+            for node in &mut self.nodes {
+                node.idle_polls = 0;
+            }
+
+            events.push(NetworkEvent::NatDelivered(packet));
+        }
+
+        Ok(events)
+    }
+
+    /// Runs rounds until `stop` returns true for an event, returning that
+    /// event, or `None` if `max_rounds` rounds pass first.
+    pub fn run_until(
+        &mut self,
+        max_rounds: usize,
+        mut stop: impl FnMut(&NetworkEvent) -> bool,
+    ) -> Result<Option<NetworkEvent>, NetworkError> {
+        for _ in 0..max_rounds {
+            if let Some(event) = self.tick()?.into_iter().find(|e| stop(e)) {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    /// Node 0 sends (5, 6) to node 1; every node bumps Y and forwards
+    /// whatever it receives to the next address.
+    const RELAY: &str = "
+            IN [address]
+            JNZ [address], #poll
+            OUT #1
+            OUT #5
+            OUT #6
+    poll:   IN [x]
+            EQ [x], #-1, [scratch]
+            JNZ [scratch], #poll
+            IN [y]
+            ADD [address], #1, [scratch]
+            OUT [scratch]
+            OUT [x]
+            ADD [y], #1, [y]
+            OUT [y]
+            JZ #0, #poll
+    address: DATA 0
+    x:       DATA 0
+    y:       DATA 0
+    scratch: DATA 0
+    ";
+
+    /// Every node but node 0 reports its address to the NAT at boot; after
+    /// that, every node sends whatever it receives to the NAT.
+    const REPORTER: &str = "
+            IN [address]
+            JZ [address], #poll
+            OUT #255
+            OUT [address]
+            MUL [address], #10, [y]
+            OUT [y]
+    poll:   IN [x]
+            EQ [x], #-1, [scratch]
+            JNZ [scratch], #poll
+            IN [y]
+            OUT #255
+            OUT [x]
+            OUT [y]
+            JZ #0, #poll
+    address: DATA 0
+    x:       DATA 0
+    y:       DATA 0
+    scratch: DATA 0
+    ";
+
+    #[test]
+    fn test_routing() {
+        let program = assemble(RELAY).unwrap();
+        let mut network = Network::new(&program, 3);
+        network.set_nat_address(3);
+
+        let event = network
+            .run_until(10, |event| match event {
+                NetworkEvent::Sent { packet, .. } => packet.destination == 3,
+                _ => false,
+            })
+            .unwrap();
+
+        assert_eq!(
+            event,
+            Some(NetworkEvent::Sent {
+                from: 2,
+                packet: Packet {
+                    destination: 3,
+                    x: 5,
+                    y: 8
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_destination() {
+        let program = assemble(RELAY).unwrap();
+        let mut network = Network::new(&program, 3);
+
+        assert_eq!(
+            network.run_until(10, |_| false),
+            Err(NetworkError::UnknownDestination {
+                from: 2,
+                packet: Packet {
+                    destination: 3,
+                    x: 5,
+                    y: 8
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_nat() {
+        let program = assemble(REPORTER).unwrap();
+        let mut network = Network::new(&program, 3);
+
+        let first = network.tick().unwrap();
+        assert_eq!(
+            first[0],
+            NetworkEvent::Sent {
+                from: 1,
+                packet: Packet {
+                    destination: 255,
+                    x: 1,
+                    y: 10
+                }
+            }
+        );
+        assert_eq!(
+            network.nat_packet(),
+            Some(Packet {
+                destination: 255,
+                x: 2,
+                y: 20
+            })
+        );
+
+        // The NAT wakes node 0 with the last packet it saw, which node 0
+        // sends straight back, so the NAT keeps delivering the same Y
+        let mut delivered = Vec::new();
+        network
+            .run_until(20, |event| {
+                if let NetworkEvent::NatDelivered(packet) = event {
+                    delivered.push(packet.y);
+                }
+
+                delivered.len() == 2
+            })
+            .unwrap();
+
+        assert_eq!(delivered, vec![20, 20]);
+    }
+
+    #[test]
+    fn test_stalled() {
+        let program = assemble("IN [0]\npoll: IN [0]\nJZ #0, #poll").unwrap();
+        let mut network = Network::new(&program, 2);
+        assert_eq!(network.run_until(10, |_| false), Err(NetworkError::Stalled));
+    }
+
+    #[test]
+    fn test_busy_nodes() {
+        // Never asks for input
+        let program = assemble("spin: JZ #0, #spin").unwrap();
+        let mut network = Network::new(&program, 2);
+        network.set_round_budget(1000);
+        assert_eq!(
+            network.tick(),
+            Err(NetworkError::Busy {
+                address: 0,
+                budget: 1000
+            })
+        );
+
+        // Sends packets forever without asking for input
+        let program = assemble("spam: OUT #1\nOUT #2\nOUT #3\nJZ #0, #spam").unwrap();
+        let mut network = Network::new(&program, 2);
+        assert_eq!(
+            network.tick(),
+            Err(NetworkError::Busy {
+                address: 0,
+                budget: DEFAULT_ROUND_BUDGET
+            })
+        );
+    }
+
+    #[test]
+    fn test_halted_nat_target() {
+        // Node 0 halts; the rest report to the NAT and then wait
+        let program = assemble(
+            "
+            IN [address]
+            JZ [address], #done
+            OUT #255
+            OUT #1
+            OUT #2
+    poll:   IN [address]
+            JZ #0, #poll
+    done:   HLT
+    address: DATA 0
+    ",
+        )
+        .unwrap();
+        let mut network = Network::new(&program, 3);
+
+        assert_eq!(
+            network.run_until(10, |_| false),
+            Err(NetworkError::NatTargetHalted)
+        );
+        assert!(network.nat_packet().is_some());
+    }
+}
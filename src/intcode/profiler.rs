@@ -0,0 +1,230 @@
+//! Finding where programs spend their time.
+//!
+//! A `Profiler` is a tracer that counts how often each instruction and each
+//! opcode runs, how often each memory cell is read and written, and how
+//! often each conditional jump is taken. Jumps taken backwards close loops,
+//! so the profiler also counts the iterations of each loop; together with the
+//! instruction counts, that shows which loops dominate a run.
+
+use super::disassembler::decode_instruction;
+use super::{opcode_info, TraceEvent, Tracer};
+use std::collections::BTreeMap;
+
+/// How often a conditional jump went each way.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCounts {
+    /// The fraction of executions that took the jump.
+    pub fn taken_ratio(&self) -> f64 {
+        self.taken as f64 / (self.taken + self.not_taken) as f64
+    }
+}
+
+/// A tracer that counts what a program does.
+#[derive(Clone, Default, Debug)]
+pub struct Profiler {
+    /// The number of instructions executed.
+    pub total: u64,
+    /// Executions of the instruction at each address.
+    pub instructions: BTreeMap<usize, u64>,
+    /// Executions of each opcode.
+    pub opcodes: BTreeMap<usize, u64>,
+    /// Reads of each memory cell through instruction parameters.
+    pub reads: BTreeMap<usize, u64>,
+    /// Writes to each memory cell.
+    pub writes: BTreeMap<usize, u64>,
+    /// The outcomes of the conditional jump at each address.
+    pub branches: BTreeMap<usize, BranchCounts>,
+    /// Iterations of each loop, by the first and last address of its body.
+    pub loops: BTreeMap<(usize, usize), u64>,
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.total += 1;
+        *self.instructions.entry(event.pc).or_insert(0) += 1;
+        *self.opcodes.entry(event.opcode).or_insert(0) += 1;
+
+        for &address in &event.reads {
+            *self.reads.entry(address).or_insert(0) += 1;
+        }
+
+        if let Some(write) = event.write {
+            *self.writes.entry(write.address).or_insert(0) += 1;
+        }
+
+        if let (5, &[test, destination]) | (6, &[test, destination]) =
+            (event.opcode, &event.operands[..])
+        {
+            let branch = self.branches.entry(event.pc).or_default();
+
+            if (test != 0) == (event.opcode == 5) {
+                branch.taken += 1;
+
+                if destination >= 0 && destination as usize <= event.pc {
+                    *self
+                        .loops
+                        .entry((destination as usize, event.pc))
+                        .or_insert(0) += 1;
+                }
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+}
+
+/// The entries of `counts` with the highest counts, most frequent first.
+fn hottest<K: Copy + Ord>(counts: &BTreeMap<K, u64>, top: usize) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = counts.iter().map(|(&key, &count)| (key, count)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(top);
+    entries
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    fn percentage(&self, count: u64) -> f64 {
+        100.0 * count as f64 / self.total.max(1) as f64
+    }
+
+    fn disassemble(memory: &[i64], address: usize) -> String {
+        decode_instruction(memory, address)
+            .map_or_else(|| "(invalid instruction)".to_string(), |i| i.to_string())
+    }
+
+    /// Describes the run, listing the `top` entries of each section with
+    /// instructions disassembled from `memory`.
+    pub fn report(&self, memory: &[i64], top: usize) -> String {
+        let mut report = format!("{} instructions executed\n", self.total);
+
+        report.push_str("\nHot spots:\n");
+        for (address, count) in hottest(&self.instructions, top) {
+            report.push_str(&format!(
+                "{:>10} {:>5.1}%  {:>6}: {}\n",
+                count,
+                self.percentage(count),
+                address,
+                Profiler::disassemble(memory, address)
+            ));
+        }
+
+        report.push_str("\nOpcodes:\n");
+        for (opcode, count) in hottest(&self.opcodes, top) {
+            report.push_str(&format!(
+                "{:>10} {:>5.1}%  {}\n",
+                count,
+                self.percentage(count),
+                opcode_info(opcode).map_or("???", |info| info.mnemonic)
+            ));
+        }
+
+        // A loop's share is that of every instruction in its body, including
+        // any loops nested inside it
+        let mut loops: Vec<((usize, usize), u64, u64)> = self
+            .loops
+            .iter()
+            .map(|(&(start, end), &iterations)| {
+                let executed = self.instructions.range(start..=end).map(|(_, &c)| c).sum();
+                ((start, end), iterations, executed)
+            })
+            .collect();
+        loops.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+
+        report.push_str("\nLoops:\n");
+        for ((start, end), iterations, executed) in loops.into_iter().take(top) {
+            report.push_str(&format!(
+                "{:>10} {:>5.1}%  {}..{}, {} iterations\n",
+                executed,
+                self.percentage(executed),
+                start,
+                end,
+                iterations
+            ));
+        }
+
+        let mut branches: Vec<(usize, BranchCounts)> =
+            self.branches.iter().map(|(&a, &b)| (a, b)).collect();
+        branches.sort_by_key(|&(address, b)| (std::cmp::Reverse(b.taken + b.not_taken), address));
+
+        report.push_str("\nBranches:\n");
+        for (address, branch) in branches.into_iter().take(top) {
+            report.push_str(&format!(
+                "{:>10} {:>5.1}%  {:>6}: {} (taken {} of {})\n",
+                branch.taken + branch.not_taken,
+                100.0 * branch.taken_ratio(),
+                address,
+                Profiler::disassemble(memory, address),
+                branch.taken,
+                branch.taken + branch.not_taken
+            ));
+        }
+
+        for (title, counts) in &[("Reads", &self.reads), ("Writes", &self.writes)] {
+            report.push_str(&format!("\n{}:\n", title));
+
+            for (address, count) in hottest(counts, top) {
+                report.push_str(&format!("{:>10}  [{}]\n", count, address));
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    // Counts address 16 down from the input to zero, then outputs it
+    const COUNTDOWN: [i64; 17] = [
+        3, 16, 1001, 16, -1, 16, 1005, 16, 2, 4, 16, 99, 0, 0, 0, 0, 0,
+    ];
+
+    fn profile(input: i64) -> Profiler {
+        let mut machine = Machine::new(&COUNTDOWN);
+        let mut profiler = Profiler::new();
+        machine.push_input(input);
+        machine.run_traced(&mut profiler).unwrap();
+        profiler
+    }
+
+    #[test]
+    fn test_counts() {
+        let profiler = profile(10);
+
+        assert_eq!(profiler.total, 23);
+        assert_eq!(profiler.instructions[&2], 10);
+        assert_eq!(profiler.instructions[&6], 10);
+        assert_eq!(profiler.opcodes[&1], 10);
+        assert_eq!(profiler.reads[&16], 21);
+        assert_eq!(profiler.writes[&16], 11);
+        assert_eq!(
+            profiler.branches[&6],
+            BranchCounts {
+                taken: 9,
+                not_taken: 1
+            }
+        );
+        assert_eq!(profiler.loops[&(2, 6)], 9);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = profile(10).report(&COUNTDOWN, 2);
+
+        assert!(report.starts_with("23 instructions executed\n\nHot spots:\n"));
+        assert!(report.contains("        10  43.5%       2: ADD [16], #-1, [16]\n"));
+        assert!(report.contains("        20  87.0%  2..6, 9 iterations\n"));
+        assert!(report.contains("        10  90.0%       6: JNZ [16], #2 (taken 9 of 10)\n"));
+        assert!(report.contains("\nReads:\n        21  [16]\n"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Searching a program's input space in parallel.
+//!
+//! Some puzzles, like day 2, ask which values to patch into a program so
+//! that it produces a particular result. A `Search` tries every combination
+//! of values for a set of addresses, spreading the runs across worker
+//! threads. The program is compiled once and shared by every worker.
+//!
+//! Results don't depend on how the work happens to be scheduled: `first`
+//! always finds the earliest matching combination, and `all` returns every
+//! match in order. Combinations are ordered like nested loops, with the
+//! first address in the outermost loop.
+
+use super::compiler::CompiledProgram;
+use super::Machine;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// The number of combinations a worker claims at a time.
+const BATCH_SIZE: usize = 64;
+
+/// The instructions each run may execute unless told otherwise, so that a
+/// combination that sends the program into an endless loop can't hang the
+/// search.
+pub const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+
+/// The most combinations a search may have. Workers claim batches past the
+/// last combination before noticing they're done, so this leaves room for
+/// them to count that far.
+const MAX_LEN: usize = usize::MAX / 2;
+
+/// A search with more combinations than can be counted.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SearchTooLarge;
+
+impl fmt::Display for SearchTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "too many combinations to search")
+    }
+}
+
+impl Error for SearchTooLarge {}
+
+/// Every combination of values for a set of addresses.
+pub struct Search<'a> {
+    program: &'a [i64],
+    addresses: Vec<usize>,
+    values: RangeInclusive<i64>,
+    /// The number of values each address can take.
+    value_count: usize,
+    len: usize,
+    threads: usize,
+    step_limit: Option<u64>,
+}
+
+impl<'a> Search<'a> {
+    /// Searches every combination of `values` at `addresses`, using as many
+    /// threads as the machine has cores. Fails if there are too many
+    /// combinations to count.
+    pub fn new(
+        program: &'a [i64],
+        addresses: &[usize],
+        values: RangeInclusive<i64>,
+    ) -> Result<Search<'a>, SearchTooLarge> {
+        let (start, end) = (*values.start() as i128, *values.end() as i128);
+        let value_count = usize::try_from((end - start + 1).max(0)).map_err(|_| SearchTooLarge)?;
+        let len = u32::try_from(addresses.len())
+            .ok()
+            .and_then(|exponent| value_count.checked_pow(exponent))
+            .filter(|&len| len <= MAX_LEN)
+            .ok_or(SearchTooLarge)?;
+
+        Ok(Search {
+            program,
+            addresses: addresses.to_vec(),
+            values,
+            value_count,
+            len,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            step_limit: Some(DEFAULT_STEP_LIMIT),
+        })
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Sets the number of instructions each run may execute, or removes the
+    /// cap if `limit` is `None`.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// The number of combinations in the search.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The combination with the given index.
+    fn combination(&self, mut index: usize) -> Vec<i64> {
+        let count = self.value_count;
+        let mut values = vec![0; self.addresses.len()];
+
+        for value in values.iter_mut().rev() {
+            *value = *self.values.start() + (index % count) as i64;
+            index /= count;
+        }
+
+        values
+    }
+
+    /// Tries combinations on every worker thread, calling `found` with the
+    /// index of each match. `found` returns a bound: combinations from there
+    /// on are no longer worth trying.
+    fn run(
+        &self,
+        matches: &(impl Fn(&Machine) -> bool + Sync),
+        found: impl Fn(usize) -> usize + Sync,
+    ) {
+        let compiled = CompiledProgram::compile(self.program, &self.addresses);
+        let next = AtomicUsize::new(0);
+        let stop = AtomicUsize::new(self.len());
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| loop {
+                    let start = next.fetch_add(BATCH_SIZE, Ordering::Relaxed);
+
+                    for index in start..start + BATCH_SIZE {
+                        // Another worker may have found something earlier
+                        if index >= stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+
+                        let mut machine = Machine::new(self.program);
+                        machine.set_step_limit(self.step_limit);
+                        for (&address, value) in self.addresses.iter().zip(self.combination(index))
+                        {
+                            machine.write(address, value);
+                        }
+
+                        // Combinations that make the program fail or run
+                        // too long simply aren't solutions
+                        if compiled.run(&mut machine).is_ok() && matches(&machine) {
+                            stop.fetch_min(found(index), Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Finds the first combination for which the program runs to completion
+    /// and `matches` accepts the finished machine. Workers stop as soon as
+    /// no earlier combination could match.
+    pub fn first(&self, matches: impl Fn(&Machine) -> bool + Sync) -> Option<Vec<i64>> {
+        let best = Mutex::new(None);
+
+        self.run(&matches, |index| {
+            let mut best = best.lock().unwrap();
+            let index = best.map_or(index, |best: usize| best.min(index));
+            *best = Some(index);
+            index
+        });
+
+        let best = best.into_inner().unwrap();
+        best.map(|index| self.combination(index))
+    }
+
+    /// Finds every combination for which the program runs to completion and
+    /// `matches` accepts the finished machine.
+    pub fn all(&self, matches: impl Fn(&Machine) -> bool + Sync) -> Vec<Vec<i64>> {
+        let found = Mutex::new(Vec::new());
+        let len = self.len();
+
+        self.run(&matches, |index| {
+            found.lock().unwrap().push(index);
+            len
+        });
+
+        let mut found = found.into_inner().unwrap();
+        found.sort_unstable();
+        found
+            .into_iter()
+            .map(|index| self.combination(index))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Day 2's sample
+    const PROGRAM: [i64; 12] = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+
+    #[test]
+    fn test_combinations() {
+        let search = Search::new(&PROGRAM, &[1, 2], 0..=11).unwrap();
+        assert_eq!(search.len(), 144);
+        assert_eq!(search.combination(0), vec![0, 0]);
+        assert_eq!(search.combination(13), vec![1, 1]);
+        assert_eq!(search.combination(143), vec![11, 11]);
+    }
+
+    #[test]
+    fn test_first_and_all() {
+        let mut search = Search::new(&PROGRAM, &[1, 2], 0..=11).unwrap();
+
+        for threads in 1..4 {
+            search.set_threads(threads);
+
+            assert_eq!(search.first(|m| m.read(0) == 3500), Some(vec![9, 10]));
+            assert_eq!(search.first(|m| m.read(0) == 7), None);
+
+            // 30 + 40 and 40 + 30 both leave 70 at address 3, which is then
+            // multiplied by address 11
+            assert_eq!(
+                search.all(|m| m.read(0) == 3500),
+                vec![vec![9, 10], vec![10, 9]]
+            );
+        }
+    }
+
+    #[test]
+    fn test_endless_combinations() {
+        // A nonzero noun jumps to the verb, so 1, 0 loops forever
+        let mut search = Search::new(&[1105, 0, 0, 99], &[1, 2], 0..=1).unwrap();
+        search.set_step_limit(Some(100));
+
+        let all = search.all(|_| true);
+        assert!(all.contains(&vec![0, 0]));
+        assert!(!all.contains(&vec![1, 0]));
+    }
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(
+            Search::new(&PROGRAM, &[1, 2], RangeInclusive::new(5, 4))
+                .unwrap()
+                .len(),
+            0
+        );
+        assert_eq!(Search::new(&PROGRAM, &[], 0..=99).unwrap().len(), 1);
+        assert_eq!(
+            Search::new(&PROGRAM, &[1], i64::MIN..=i64::MAX).err(),
+            Some(SearchTooLarge)
+        );
+        assert_eq!(
+            Search::new(&PROGRAM, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11], 0..=99).err(),
+            Some(SearchTooLarge)
+        );
+    }
+}
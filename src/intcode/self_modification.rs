@@ -0,0 +1,213 @@
+//! Detecting programs that modify their own code.
+//!
+//! Intcode doesn't separate code from data, and plenty of programs write
+//! over their own instructions: day 2's writes its results into the words of
+//! instructions it has already run. That matters to anything that assumes
+//! code stays put, such as the compiler.
+//!
+//! A `SelfModificationDetector` is a tracer that remembers which words have
+//! been executed and which have been written, and reports two things: writes
+//! into words that have already run as part of an instruction, and
+//! instructions that run from words the program wrote itself.
+
+use super::{opcode_info, TraceEvent, Tracer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// A single sign of self-modification.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Finding {
+    /// The instruction at `pc` wrote to `address`, which had already been
+    /// executed as part of the instruction at `instruction`.
+    CodeOverwritten {
+        pc: usize,
+        address: usize,
+        instruction: usize,
+    },
+    /// The instruction at `pc` ran with its word at `address` having been
+    /// written by the instruction at `written_by`.
+    WrittenCodeExecuted {
+        pc: usize,
+        address: usize,
+        written_by: usize,
+    },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::CodeOverwritten {
+                pc,
+                address,
+                instruction,
+            } => write!(
+                f,
+                "instruction at {} wrote to word {}, part of the instruction at {} that already ran",
+                pc, address, instruction
+            ),
+            Finding::WrittenCodeExecuted {
+                pc,
+                address,
+                written_by,
+            } => write!(
+                f,
+                "instruction at {} ran from word {}, written by the instruction at {}",
+                pc, address, written_by
+            ),
+        }
+    }
+}
+
+/// A tracer that watches for self-modifying code.
+#[derive(Clone, Default, Debug)]
+pub struct SelfModificationDetector {
+    /// For each word executed so far, the instruction it last ran as part of.
+    executed: HashMap<usize, usize>,
+    /// For each word written so far, the instruction that last wrote it.
+    written: HashMap<usize, usize>,
+    /// How many times each finding happened.
+    findings: BTreeMap<Finding, u64>,
+}
+
+impl SelfModificationDetector {
+    pub fn new() -> SelfModificationDetector {
+        SelfModificationDetector::default()
+    }
+
+    /// Whether the program ran without modifying its own code.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Every distinct finding, with the number of times it happened.
+    pub fn findings(&self) -> &BTreeMap<Finding, u64> {
+        &self.findings
+    }
+
+    /// Describes the findings, one per line.
+    pub fn report(&self) -> String {
+        if self.is_clean() {
+            return "No self-modifying code detected\n".to_string();
+        }
+
+        let mut report = String::new();
+
+        for (finding, &count) in &self.findings {
+            if count == 1 {
+                report.push_str(&format!("{}\n", finding));
+            } else {
+                report.push_str(&format!("{} ({} times)\n", finding, count));
+            }
+        }
+
+        report
+    }
+}
+
+impl Tracer for SelfModificationDetector {
+    fn trace(&mut self, event: &TraceEvent) {
+        let len = opcode_info(event.opcode).map_or(1, |info| info.parameter_count + 1);
+
+        for address in event.pc..event.pc + len {
+            if let Some(&written_by) = self.written.get(&address) {
+                let finding = Finding::WrittenCodeExecuted {
+                    pc: event.pc,
+                    address,
+                    written_by,
+                };
+                *self.findings.entry(finding).or_insert(0) += 1;
+            }
+
+            self.executed.insert(address, event.pc);
+        }
+
+        if let Some(write) = event.write {
+            if let Some(&instruction) = self.executed.get(&write.address) {
+                let finding = Finding::CodeOverwritten {
+                    pc: event.pc,
+                    address: write.address,
+                    instruction,
+                };
+                *self.findings.entry(finding).or_insert(0) += 1;
+            }
+
+            self.written.insert(write.address, event.pc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    fn detect(program: &[i64], inputs: &[i64]) -> SelfModificationDetector {
+        let mut machine = Machine::new(program);
+        let mut detector = SelfModificationDetector::new();
+
+        for &input in inputs {
+            machine.push_input(input);
+        }
+
+        machine.run_traced(&mut detector).unwrap();
+        detector
+    }
+
+    #[test]
+    fn test_clean_program() {
+        // Day 5's comparison sample keeps its data away from its code
+        let detector = detect(&[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], &[8]);
+        assert!(detector.is_clean());
+        assert_eq!(detector.report(), "No self-modifying code detected\n");
+    }
+
+    #[test]
+    fn test_overwritten_code() {
+        // Day 2's sample writes into both of its instructions
+        let detector = detect(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], &[]);
+        let findings: Vec<Finding> = detector.findings().keys().copied().collect();
+
+        assert_eq!(
+            findings,
+            vec![
+                Finding::CodeOverwritten {
+                    pc: 0,
+                    address: 3,
+                    instruction: 0
+                },
+                Finding::CodeOverwritten {
+                    pc: 4,
+                    address: 0,
+                    instruction: 0
+                },
+            ]
+        );
+        assert_eq!(
+            detector.report(),
+            "instruction at 0 wrote to word 3, part of the instruction at 0 that already ran\n\
+             instruction at 4 wrote to word 0, part of the instruction at 0 that already ran\n"
+        );
+    }
+
+    #[test]
+    fn test_written_code_executed() {
+        // Turns its halt into an output, then runs it twice
+        let program = [1101, 0, 104, 8, 1105, 1, 8, 99, 99, 7, 1105, 1, 8];
+        let mut machine = Machine::new(&program);
+        let mut detector = SelfModificationDetector::new();
+
+        for _ in 0..6 {
+            machine.step_traced(&mut detector).unwrap();
+        }
+
+        let written = Finding::WrittenCodeExecuted {
+            pc: 8,
+            address: 8,
+            written_by: 0,
+        };
+        assert_eq!(detector.findings().get(&written), Some(&2));
+        assert!(detector.report().contains(
+            "instruction at 8 ran from word 8, written by the instruction at 0 (2 times)"
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+//! Saving and restoring paused machines.
+//!
+//! Snapshots are plain text, one field per line, so they can be attached to
+//! bug reports and diffed:
+//!
+//! ```text
+//! intcode-snapshot 1
+//! pc 6
+//! relative_base 0
+//! halted 0
+//! steps 2
+//! input 5,6
+//! output
+//! memory 3,9,8,9,10,9,4,9,99,1,8
+//! ```
+//!
+//! The first line names the format version. Lists are comma-separated and may
+//! be empty. A machine's memory and step limits are settings rather than
+//! state, so they aren't saved; restored machines use the defaults, with
+//! cycle detection off.
+
+use super::{Machine, DEFAULT_MEMORY_LIMIT};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+/// The first word of every snapshot.
+pub const HEADER: &str = "intcode-snapshot";
+
+/// The snapshot format version written by `save`.
+pub const VERSION: u32 = 1;
+
+/// A reason a snapshot couldn't be restored.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SnapshotError {
+    /// The text doesn't start with the snapshot header.
+    NotASnapshot,
+    /// The snapshot was written by an unknown version of the format.
+    UnsupportedVersion(String),
+    /// A required field is missing.
+    MissingField(&'static str),
+    /// A field appears more than once or isn't part of the format.
+    UnexpectedField(String),
+    /// A field's value couldn't be parsed.
+    InvalidField { field: String, value: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not an Intcode snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::MissingField(field) => write!(f, "missing field {}", field),
+            SnapshotError::UnexpectedField(field) => write!(f, "unexpected field {}", field),
+            SnapshotError::InvalidField { field, value } => {
+                write!(f, "invalid value {} for field {}", value, field)
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+fn list_field(name: &str, values: impl Iterator<Item = i64>) -> String {
+    let values: Vec<String> = values.map(|v| v.to_string()).collect();
+
+    if values.is_empty() {
+        format!("{}\n", name)
+    } else {
+        format!("{} {}\n", name, values.join(","))
+    }
+}
+
+/// Serializes a machine's state.
+pub fn save(machine: &Machine) -> String {
+    let mut snapshot = format!(
+        "{} {}\n\
+         pc {}\n\
+         relative_base {}\n\
+         halted {}\n\
+         steps {}\n",
+        HEADER,
+        VERSION,
+        machine.program_counter,
+        machine.relative_base,
+        machine.halted as u8,
+        machine.steps,
+    );
+
+    snapshot.push_str(&list_field("input", machine.input.iter().copied()));
+    snapshot.push_str(&list_field("output", machine.output.iter().copied()));
+    snapshot.push_str(&list_field("memory", machine.memory.iter().copied()));
+    snapshot
+}
+
+/// Whether `text` looks like a snapshot rather than a program.
+pub fn is_snapshot(text: &str) -> bool {
+    text.trim_start().starts_with(HEADER)
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str, value: &str) -> Result<T, SnapshotError> {
+    value.parse::<T>().map_err(|_| SnapshotError::InvalidField {
+        field: field.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn parse_list(field: &str, value: &str) -> Result<Vec<i64>, SnapshotError> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    value
+        .split(',')
+        .map(|v| parse_field(field, v.trim()))
+        .collect()
+}
+
+/// Rebuilds a machine from a snapshot produced by `save`.
+pub fn restore(text: &str) -> Result<Machine, SnapshotError> {
+    let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+
+    let header = lines.next().ok_or(SnapshotError::NotASnapshot)?;
+    let version = match header.split_once(' ') {
+        Some((HEADER, version)) => version.trim(),
+        _ => return Err(SnapshotError::NotASnapshot),
+    };
+
+    if version != VERSION.to_string() {
+        return Err(SnapshotError::UnsupportedVersion(version.to_string()));
+    }
+
+    let mut program_counter = None;
+    let mut relative_base = None;
+    let mut halted = None;
+    let mut steps = None;
+    let mut input = None;
+    let mut output = None;
+    let mut memory = None;
+
+    for line in lines {
+        let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+
+        let duplicate = match field {
+            "pc" => program_counter
+                .replace(parse_field(field, value)?)
+                .is_some(),
+            "relative_base" => relative_base.replace(parse_field(field, value)?).is_some(),
+            "halted" => halted
+                .replace(parse_field::<u8>(field, value)? != 0)
+                .is_some(),
+            "steps" => steps.replace(parse_field(field, value)?).is_some(),
+            "input" => input.replace(parse_list(field, value)?).is_some(),
+            "output" => output.replace(parse_list(field, value)?).is_some(),
+            "memory" => memory.replace(parse_list(field, value)?).is_some(),
+            _ => true,
+        };
+
+        if duplicate {
+            return Err(SnapshotError::UnexpectedField(field.to_string()));
+        }
+    }
+
+    Ok(Machine {
+        memory: memory.ok_or(SnapshotError::MissingField("memory"))?,
+        program_counter: program_counter.ok_or(SnapshotError::MissingField("pc"))?,
+        relative_base: relative_base.ok_or(SnapshotError::MissingField("relative_base"))?,
+        input: input
+            .map(VecDeque::from)
+            .ok_or(SnapshotError::MissingField("input"))?,
+        output: output.ok_or(SnapshotError::MissingField("output"))?,
+        halted: halted.ok_or(SnapshotError::MissingField("halted"))?,
+        memory_limit: DEFAULT_MEMORY_LIMIT,
+        step_limit: None,
+        steps: steps.ok_or(SnapshotError::MissingField("steps"))?,
+        decoded: Vec::new(),
+        cache_decoding: true,
+        cycles: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Status;
+
+    #[test]
+    fn test_round_trip() {
+        // Adds pairs of inputs until it sees a zero
+        let program = [
+            3, 17, 1006, 17, 16, 3, 18, 1, 17, 18, 19, 4, 19, 1105, 1, 0, 99, 0, 0, 0,
+        ];
+
+        let mut machine = Machine::new(&program);
+        machine.push_input(2);
+        machine.push_input(3);
+        machine.push_input(10);
+        assert_eq!(machine.resume().unwrap(), Status::Output(5));
+        assert_eq!(machine.resume().unwrap(), Status::NeedsInput);
+
+        let snapshot = save(&machine);
+        assert!(is_snapshot(&snapshot));
+        assert!(snapshot.starts_with("intcode-snapshot 1\npc 5\n"));
+        assert!(snapshot.contains("\ninput\n"));
+        assert!(snapshot.contains("\noutput 5\n"));
+
+        let mut restored = restore(&snapshot).unwrap();
+        assert_eq!(save(&restored), snapshot);
+
+        for machine in [&mut machine, &mut restored].iter_mut() {
+            machine.push_input(1);
+            machine.push_input(0);
+            machine.run().unwrap();
+        }
+
+        assert_eq!(restored.output(), &[5, 11]);
+        assert_eq!(restored.output(), machine.output());
+        assert_eq!(restored.steps(), machine.steps());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(restore("1,2,3").err(), Some(SnapshotError::NotASnapshot));
+        assert_eq!(
+            restore("intcode-snapshot 2\n").err(),
+            Some(SnapshotError::UnsupportedVersion("2".to_string()))
+        );
+        assert_eq!(
+            restore("intcode-snapshot 1\npc 0\n").err(),
+            Some(SnapshotError::MissingField("memory"))
+        );
+        assert_eq!(
+            restore("intcode-snapshot 1\npc 0\npc 1\n").err(),
+            Some(SnapshotError::UnexpectedField("pc".to_string()))
+        );
+        assert_eq!(
+            restore("intcode-snapshot 1\nregisters 4\n").err(),
+            Some(SnapshotError::UnexpectedField("registers".to_string()))
+        );
+        assert_eq!(
+            restore("intcode-snapshot 1\nmemory 1,x\n").err(),
+            Some(SnapshotError::InvalidField {
+                field: "memory".to_string(),
+                value: "x".to_string()
+            })
+        );
+    }
+}
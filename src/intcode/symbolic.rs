@@ -0,0 +1,787 @@
+//! Running programs over polynomials instead of numbers.
+//!
+//! Some programs, like day 2's, are straight-line arithmetic on a few input
+//! words. Marking those words as variables and running the program
+//! symbolically gives every memory cell as a polynomial in them, which can
+//! then be solved for a target instead of searched for by brute force.
+//!
+//! Not everything can be followed symbolically. Reading through an address
+//! that depends on a variable gives an opaque value: harmless if it's
+//! overwritten before anything uses it, but anything computed from it is
+//! opaque too. Writing or jumping to such an address, branching on a
+//! symbolic value and doing input or output all stop the run with an error,
+//! as does building a polynomial too big to be worth following, such as one
+//! squared over and over in a loop.
+
+use super::{decode_opcode, get_mode, ParameterMode, DEFAULT_MEMORY_LIMIT};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// The highest total degree a symbolic run will build a term up to.
+pub const MAX_DEGREE: u32 = 64;
+
+/// The most terms a symbolic run will build a polynomial up to.
+pub const MAX_TERMS: usize = 1024;
+
+/// The power each variable in a term is raised to, keyed by variable, so
+/// `x0 * x0 * x1` is `{0: 2, 1: 1}` and a constant is `{}`. Variables to the
+/// power zero aren't stored.
+type Monomial = BTreeMap<usize, u32>;
+
+fn degree(monomial: &Monomial) -> u32 {
+    monomial.values().sum()
+}
+
+/// A polynomial with integer coefficients over numbered variables.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Polynomial {
+    /// Coefficients keyed by monomial. Zero coefficients aren't stored.
+    terms: BTreeMap<Monomial, i64>,
+}
+
+impl Polynomial {
+    pub fn constant(value: i64) -> Polynomial {
+        let mut polynomial = Polynomial::default();
+
+        if value != 0 {
+            polynomial.terms.insert(Monomial::new(), value);
+        }
+
+        polynomial
+    }
+
+    pub fn variable(index: usize) -> Polynomial {
+        let mut polynomial = Polynomial::default();
+        let mut monomial = Monomial::new();
+        monomial.insert(index, 1);
+        polynomial.terms.insert(monomial, 1);
+        polynomial
+    }
+
+    /// The polynomial's value, if it doesn't depend on any variable.
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((monomial, &coefficient)) if monomial.is_empty() && self.terms.len() == 1 => {
+                Some(coefficient)
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds two polynomials, or returns `None` if a coefficient overflows.
+    pub fn checked_add(&self, other: &Polynomial) -> Option<Polynomial> {
+        let mut sum = self.clone();
+
+        for (monomial, &coefficient) in &other.terms {
+            let entry = sum.terms.entry(monomial.clone()).or_insert(0);
+            *entry = entry.checked_add(coefficient)?;
+
+            if *entry == 0 {
+                sum.terms.remove(monomial);
+            }
+        }
+
+        Some(sum)
+    }
+
+    /// Multiplies two polynomials, or returns `None` if a coefficient or
+    /// an exponent overflows.
+    pub fn checked_mul(&self, other: &Polynomial) -> Option<Polynomial> {
+        let mut product = Polynomial::default();
+
+        for (lhs_monomial, &lhs) in &self.terms {
+            for (rhs_monomial, &rhs) in &other.terms {
+                let mut monomial = lhs_monomial.clone();
+
+                for (&variable, &power) in rhs_monomial {
+                    let entry = monomial.entry(variable).or_insert(0);
+                    *entry = entry.checked_add(power)?;
+                }
+
+                let coefficient = lhs.checked_mul(rhs)?;
+                let entry = product.terms.entry(monomial.clone()).or_insert(0);
+                *entry = entry.checked_add(coefficient)?;
+
+                if *entry == 0 {
+                    product.terms.remove(&monomial);
+                }
+            }
+        }
+
+        Some(product)
+    }
+
+    /// The highest total degree of any term.
+    pub fn degree(&self) -> u32 {
+        self.terms.keys().map(degree).max().unwrap_or(0)
+    }
+
+    /// The number of terms with a nonzero coefficient.
+    pub fn term_count(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// The polynomial's value with each variable set to the value at its
+    /// index, or `None` if the calculation overflows.
+    pub fn evaluate(&self, values: &[i64]) -> Option<i64> {
+        self.terms
+            .iter()
+            .try_fold(0i64, |total, (monomial, &coefficient)| {
+                let term = monomial
+                    .iter()
+                    .try_fold(coefficient, |term, (&variable, &power)| {
+                        term.checked_mul(values[variable].checked_pow(power)?)
+                    })?;
+                total.checked_add(term)
+            })
+    }
+
+    /// The highest power of `variable` in any term.
+    fn degree_in(&self, variable: usize) -> u32 {
+        self.terms
+            .keys()
+            .filter_map(|monomial| monomial.get(&variable).copied())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Splits the polynomial into the terms containing `variable` (with that
+    /// variable divided out) and the rest. Only meaningful when the
+    /// polynomial is linear in `variable`.
+    fn split_linear(&self, variable: usize) -> (Polynomial, Polynomial) {
+        let mut coefficient = Polynomial::default();
+        let mut rest = Polynomial::default();
+
+        for (monomial, &value) in &self.terms {
+            if monomial.contains_key(&variable) {
+                let mut monomial = monomial.clone();
+                monomial.remove(&variable);
+                coefficient.terms.insert(monomial, value);
+            } else {
+                rest.terms.insert(monomial.clone(), value);
+            }
+        }
+
+        (coefficient, rest)
+    }
+
+    /// Finds every assignment of the variables, each drawn from its range,
+    /// for which the polynomial equals `target`. Assignments are listed in
+    /// nested-loop order with the first variable outermost.
+    ///
+    /// The last variable is solved for directly whenever the polynomial is
+    /// linear in it, so a polynomial in two variables only has to be
+    /// evaluated once per value of the first.
+    pub fn solve(&self, target: i64, ranges: &[RangeInclusive<i64>]) -> Vec<Vec<i64>> {
+        let mut solutions = Vec::new();
+        let mut values = vec![0; ranges.len()];
+        self.solve_from(target, ranges, &mut values, 0, &mut solutions);
+        solutions
+    }
+
+    fn solve_from(
+        &self,
+        target: i64,
+        ranges: &[RangeInclusive<i64>],
+        values: &mut Vec<i64>,
+        variable: usize,
+        solutions: &mut Vec<Vec<i64>>,
+    ) {
+        if variable == ranges.len() {
+            if self.evaluate(values) == Some(target) {
+                solutions.push(values.clone());
+            }
+
+            return;
+        }
+
+        let range = ranges[variable].clone();
+
+        if variable + 1 == ranges.len() && self.degree_in(variable) == 1 {
+            // target = a * x + b, so x = (target - b) / a
+            let (coefficient, rest) = self.split_linear(variable);
+            let a = coefficient.evaluate(values);
+            let b = rest.evaluate(values);
+
+            match (a, b.and_then(|b| target.checked_sub(b))) {
+                // The other variables cancel this one out
+                (Some(0), Some(0)) => {
+                    for value in range {
+                        values[variable] = value;
+                        solutions.push(values.clone());
+                    }
+                }
+                (Some(a), Some(difference))
+                    if a != 0 && difference % a == 0 && range.contains(&(difference / a)) =>
+                {
+                    values[variable] = difference / a;
+                    solutions.push(values.clone());
+                }
+                _ => {}
+            }
+
+            return;
+        }
+
+        for value in range {
+            values[variable] = value;
+            self.solve_from(target, ranges, values, variable + 1, solutions);
+        }
+    }
+
+    /// Writes the polynomial out using `names` for the variables, falling
+    /// back to `x0`, `x1` and so on for variables without one.
+    pub fn format(&self, names: &[&str]) -> String {
+        if self.terms.is_empty() {
+            return "0".to_string();
+        }
+
+        let name = |variable: usize| match names.get(variable) {
+            Some(name) => name.to_string(),
+            None => format!("x{}", variable),
+        };
+
+        // Highest degree first, with the constant term last. Terms of the
+        // same degree go in order of their powers of x0, then x1 and so on.
+        let powers = |monomial: &Monomial| {
+            let variables = monomial.keys().next_back().map_or(0, |&v| v + 1);
+            (0..variables)
+                .map(|v| monomial.get(&v).copied().unwrap_or(0))
+                .collect::<Vec<_>>()
+        };
+        let mut terms: Vec<(&Monomial, i64)> = self.terms.iter().map(|(m, &c)| (m, c)).collect();
+        terms.sort_by(|(a, _), (b, _)| {
+            degree(b)
+                .cmp(&degree(a))
+                .then_with(|| powers(b).cmp(&powers(a)))
+        });
+
+        let mut text = String::new();
+
+        for (index, (monomial, coefficient)) in terms.into_iter().enumerate() {
+            let sign = if coefficient < 0 { "-" } else { "+" };
+
+            if index == 0 {
+                if coefficient < 0 {
+                    text.push('-');
+                }
+            } else {
+                text.push_str(&format!(" {} ", sign));
+            }
+
+            let magnitude = coefficient.unsigned_abs();
+            let mut factors: Vec<String> = monomial
+                .iter()
+                .map(|(&v, &power)| match power {
+                    1 => name(v),
+                    _ => format!("{}^{}", name(v), power),
+                })
+                .collect();
+
+            if magnitude != 1 || factors.is_empty() {
+                factors.insert(0, magnitude.to_string());
+            }
+
+            text.push_str(&factors.join("*"));
+        }
+
+        text
+    }
+}
+
+/// The contents of a memory cell during a symbolic run.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Value {
+    Known(Polynomial),
+    /// Something that can't be expressed as a polynomial, such as a read
+    /// through an address that depends on a variable.
+    Opaque,
+}
+
+impl Value {
+    fn constant(&self) -> Option<i64> {
+        match self {
+            Value::Known(polynomial) => polynomial.as_constant(),
+            Value::Opaque => None,
+        }
+    }
+}
+
+/// A reason a program couldn't be run symbolically.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SymbolicError {
+    /// The opcode itself depends on a variable.
+    SymbolicInstruction {
+        pc: usize,
+    },
+    /// An instruction writes or jumps to an address that depends on a
+    /// variable.
+    SymbolicAddress {
+        pc: usize,
+    },
+    /// A conditional jump's test depends on a variable.
+    SymbolicBranch {
+        pc: usize,
+    },
+    /// The instruction doesn't exist, is malformed, or does input or output.
+    Unsupported {
+        pc: usize,
+        instruction: i64,
+    },
+    /// An instruction used an address outside of memory.
+    InvalidAddress {
+        pc: usize,
+        address: i64,
+    },
+    /// Working out the relative base or an address relative to it
+    /// overflowed.
+    Overflow {
+        pc: usize,
+    },
+    /// The instruction built a polynomial of more than `MAX_DEGREE` or with
+    /// more than `MAX_TERMS` terms.
+    TooComplex {
+        pc: usize,
+    },
+    StepLimitExceeded {
+        limit: u64,
+    },
+    /// The value asked for can't be expressed as a polynomial.
+    OpaqueValue {
+        address: usize,
+    },
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::SymbolicInstruction { pc } => {
+                write!(f, "the instruction at {} depends on the input", pc)
+            }
+            SymbolicError::SymbolicAddress { pc } => write!(
+                f,
+                "the instruction at {} uses an address that depends on the input",
+                pc
+            ),
+            SymbolicError::SymbolicBranch { pc } => {
+                write!(f, "the jump at {} depends on the input", pc)
+            }
+            SymbolicError::Unsupported { pc, instruction } => write!(
+                f,
+                "can't run instruction {} at {} symbolically",
+                instruction, pc
+            ),
+            SymbolicError::InvalidAddress { pc, address } => {
+                write!(
+                    f,
+                    "the instruction at {} uses invalid address {}",
+                    pc, address
+                )
+            }
+            SymbolicError::Overflow { pc } => {
+                write!(f, "arithmetic overflow at {}", pc)
+            }
+            SymbolicError::TooComplex { pc } => {
+                write!(f, "the instruction at {} builds too large a polynomial", pc)
+            }
+            SymbolicError::StepLimitExceeded { limit } => {
+                write!(f, "still running after {} steps", limit)
+            }
+            SymbolicError::OpaqueValue { address } => {
+                write!(
+                    f,
+                    "the value at {} isn't a polynomial of the input",
+                    address
+                )
+            }
+        }
+    }
+}
+
+impl Error for SymbolicError {}
+
+/// Applies an arithmetic or comparison opcode to two polynomials, or returns
+/// `None` if the result isn't a polynomial.
+fn arithmetic(opcode: usize, lhs: &Polynomial, rhs: &Polynomial) -> Option<Polynomial> {
+    match opcode {
+        1 => lhs.checked_add(rhs),
+        2 => lhs.checked_mul(rhs),
+        // Anything equals itself, whatever the variables are
+        8 if lhs == rhs => Some(Polynomial::constant(1)),
+        _ => {
+            let (lhs, rhs) = (lhs.as_constant()?, rhs.as_constant()?);
+            let result = if opcode == 7 { lhs < rhs } else { lhs == rhs };
+            Some(Polynomial::constant(result as i64))
+        }
+    }
+}
+
+/// A machine whose memory holds polynomials.
+#[derive(Clone, Debug)]
+pub struct SymbolicMachine {
+    memory: Vec<Value>,
+    program_counter: usize,
+    relative_base: i64,
+}
+
+impl SymbolicMachine {
+    /// Loads `program`, replacing the word at each of `variables` with a
+    /// variable numbered by its position in the list.
+    pub fn new(program: &[i64], variables: &[usize]) -> SymbolicMachine {
+        let mut memory: Vec<Value> = program
+            .iter()
+            .map(|&word| Value::Known(Polynomial::constant(word)))
+            .collect();
+
+        for (index, &address) in variables.iter().enumerate() {
+            if address >= memory.len() {
+                memory.resize(address + 1, Value::Known(Polynomial::default()));
+            }
+
+            memory[address] = Value::Known(Polynomial::variable(index));
+        }
+
+        SymbolicMachine {
+            memory,
+            program_counter: 0,
+            relative_base: 0,
+        }
+    }
+
+    fn read(&self, address: usize) -> Value {
+        self.memory
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| Value::Known(Polynomial::default()))
+    }
+
+    fn to_address(&self, address: i64) -> Result<usize, SymbolicError> {
+        if address < 0 || address as usize >= DEFAULT_MEMORY_LIMIT {
+            return Err(SymbolicError::InvalidAddress {
+                pc: self.program_counter,
+                address,
+            });
+        }
+
+        Ok(address as usize)
+    }
+
+    /// The address a parameter refers to, or `None` if it depends on a
+    /// variable.
+    fn address(&self, mode: ParameterMode, index: usize) -> Result<Option<usize>, SymbolicError> {
+        let word = match self.read(self.program_counter + index + 1).constant() {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+
+        match mode {
+            ParameterMode::Immediate => Ok(None),
+            ParameterMode::Position => self.to_address(word).map(Some),
+            ParameterMode::Relative => {
+                let address =
+                    self.relative_base
+                        .checked_add(word)
+                        .ok_or(SymbolicError::Overflow {
+                            pc: self.program_counter,
+                        })?;
+                self.to_address(address).map(Some)
+            }
+        }
+    }
+
+    fn parameter(&self, modes: &[ParameterMode], index: usize) -> Result<Value, SymbolicError> {
+        let mode = get_mode(modes, index);
+
+        if mode == ParameterMode::Immediate {
+            return Ok(self.read(self.program_counter + index + 1));
+        }
+
+        Ok(match self.address(mode, index)? {
+            Some(address) => self.read(address),
+            None => Value::Opaque,
+        })
+    }
+
+    fn target(&self, modes: &[ParameterMode], index: usize) -> Result<usize, SymbolicError> {
+        match get_mode(modes, index) {
+            ParameterMode::Immediate => Err(self.unsupported()),
+            mode => self
+                .address(mode, index)?
+                .ok_or(SymbolicError::SymbolicAddress {
+                    pc: self.program_counter,
+                }),
+        }
+    }
+
+    fn unsupported(&self) -> SymbolicError {
+        SymbolicError::Unsupported {
+            pc: self.program_counter,
+            instruction: self.read(self.program_counter).constant().unwrap_or(0),
+        }
+    }
+
+    /// Runs the program until it halts, or gives up after `step_limit`
+    /// instructions.
+    pub fn run(&mut self, step_limit: u64) -> Result<(), SymbolicError> {
+        for _ in 0..step_limit {
+            let pc = self.program_counter;
+
+            if pc >= self.memory.len() {
+                return Ok(());
+            }
+
+            let instruction = self
+                .read(pc)
+                .constant()
+                .ok_or(SymbolicError::SymbolicInstruction { pc })?;
+
+            if instruction < 0 {
+                return Err(self.unsupported());
+            }
+
+            let (opcode, modes) =
+                decode_opcode(instruction as usize).map_err(|_| self.unsupported())?;
+
+            match opcode {
+                1 | 2 | 7 | 8 => {
+                    let lhs = self.parameter(&modes, 0)?;
+                    let rhs = self.parameter(&modes, 1)?;
+                    let target = self.target(&modes, 2)?;
+
+                    let value = match (lhs, rhs) {
+                        (Value::Known(lhs), Value::Known(rhs)) => arithmetic(opcode, &lhs, &rhs),
+                        _ => None,
+                    };
+
+                    if let Some(value) = &value {
+                        if value.degree() > MAX_DEGREE || value.term_count() > MAX_TERMS {
+                            return Err(SymbolicError::TooComplex { pc });
+                        }
+                    }
+
+                    if target >= self.memory.len() {
+                        self.memory
+                            .resize(target + 1, Value::Known(Polynomial::default()));
+                    }
+
+                    self.memory[target] = value.map_or(Value::Opaque, Value::Known);
+                    self.program_counter += 4;
+                }
+                5 | 6 => {
+                    let test = self.parameter(&modes, 0)?;
+                    let test = test
+                        .constant()
+                        .ok_or(SymbolicError::SymbolicBranch { pc })?;
+
+                    if (test == 0) == (opcode == 6) {
+                        let destination = self.parameter(&modes, 1)?;
+                        let destination = destination
+                            .constant()
+                            .ok_or(SymbolicError::SymbolicAddress { pc })?;
+
+                        self.program_counter = self.to_address(destination)?;
+                    } else {
+                        self.program_counter += 3;
+                    }
+                }
+                9 => {
+                    let offset = self.parameter(&modes, 0)?;
+                    let offset = offset
+                        .constant()
+                        .ok_or(SymbolicError::SymbolicAddress { pc })?;
+                    self.relative_base = self
+                        .relative_base
+                        .checked_add(offset)
+                        .ok_or(SymbolicError::Overflow { pc })?;
+                    self.program_counter += 2;
+                }
+                99 => return Ok(()),
+                _ => return Err(self.unsupported()),
+            }
+        }
+
+        Err(SymbolicError::StepLimitExceeded { limit: step_limit })
+    }
+
+    /// The polynomial stored at `address`.
+    pub fn value(&self, address: usize) -> Result<Polynomial, SymbolicError> {
+        match self.read(address) {
+            Value::Known(polynomial) => Ok(polynomial),
+            Value::Opaque => Err(SymbolicError::OpaqueValue { address }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    #[test]
+    fn test_polynomial_arithmetic() {
+        let x = Polynomial::variable(0);
+        let y = Polynomial::variable(1);
+        let two = Polynomial::constant(2);
+
+        // (x + 2) * (x - y) = x^2 - xy + 2x - 2y
+        let lhs = x.checked_add(&two).unwrap();
+        let rhs = x
+            .checked_add(&y.checked_mul(&Polynomial::constant(-1)).unwrap())
+            .unwrap();
+        let product = lhs.checked_mul(&rhs).unwrap();
+
+        assert_eq!(product.format(&["x", "y"]), "x^2 - x*y + 2*x - 2*y");
+        assert_eq!(product.evaluate(&[3, 1]), Some(10));
+        assert_eq!(product.as_constant(), None);
+        assert_eq!(two.checked_mul(&two).unwrap().as_constant(), Some(4));
+        assert_eq!(x.checked_add(&x).unwrap().format(&[]), "2*x0");
+        assert_eq!(Polynomial::constant(-7).format(&[]), "-7");
+        assert_eq!(Polynomial::default().format(&[]), "0");
+    }
+
+    #[test]
+    fn test_solve() {
+        let x = Polynomial::variable(0);
+        let y = Polynomial::variable(1);
+
+        // 8x + 7y
+        let linear = x
+            .checked_mul(&Polynomial::constant(8))
+            .unwrap()
+            .checked_add(&y.checked_mul(&Polynomial::constant(7)).unwrap())
+            .unwrap();
+        assert_eq!(linear.solve(59, &[0..=9, 0..=9]), vec![vec![3, 5]]);
+        assert_eq!(
+            linear.solve(56, &[0..=9, 0..=9]),
+            vec![vec![0, 8], vec![7, 0]]
+        );
+
+        // xy, which isn't linear in y once x is 0
+        let product = x.checked_mul(&y).unwrap();
+        assert_eq!(
+            product.solve(6, &[1..=6, 1..=6]),
+            vec![vec![1, 6], vec![2, 3], vec![3, 2], vec![6, 1]]
+        );
+        assert_eq!(product.solve(0, &[0..=1, 0..=1]).len(), 3);
+
+        // y * y
+        let square = y.checked_mul(&y).unwrap();
+        assert_eq!(
+            square.solve(9, &[0..=0, -5..=5]),
+            vec![vec![0, -3], vec![0, 3]]
+        );
+    }
+
+    #[test]
+    fn test_day_two_style_program() {
+        // Like real day 2 inputs, the first instruction reads through the
+        // noun and verb as addresses, but its result is overwritten
+        let program = [
+            1, 0, 0, 3, // [3] = [noun] + [verb]
+            1, 1, 2, 3, // [3] = noun + verb
+            2, 3, 17, 3, // [3] = [3] * 7
+            1, 3, 1, 0, // [0] = [3] + noun
+            99, 7,
+        ];
+
+        let mut machine = SymbolicMachine::new(&program, &[1, 2]);
+        machine.run(100).unwrap();
+
+        let polynomial = machine.value(0).unwrap();
+        assert_eq!(polynomial.format(&["noun", "verb"]), "8*noun + 7*verb");
+        assert_eq!(polynomial.solve(59, &[0..=9, 0..=9]), vec![vec![3, 5]]);
+
+        let mut concrete = Machine::new(&program);
+        concrete.write(1, 3);
+        concrete.write(2, 5);
+        concrete.run().unwrap();
+        assert_eq!(concrete.read(0), 59);
+
+        // The first instruction's result is opaque until it's overwritten
+        let mut machine = SymbolicMachine::new(&program, &[1, 2]);
+        assert_eq!(
+            machine.run(1),
+            Err(SymbolicError::StepLimitExceeded { limit: 1 })
+        );
+        assert_eq!(
+            machine.value(3),
+            Err(SymbolicError::OpaqueValue { address: 3 })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let run = |program: &[i64]| SymbolicMachine::new(program, &[1]).run(100);
+
+        // Branches on the variable
+        assert_eq!(
+            run(&[1105, 0, 4, 99, 99]),
+            Err(SymbolicError::SymbolicBranch { pc: 0 })
+        );
+        assert_eq!(
+            run(&[3, 0, 99]),
+            Err(SymbolicError::Unsupported {
+                pc: 0,
+                instruction: 3
+            })
+        );
+        // Writes through the variable
+        assert_eq!(
+            SymbolicMachine::new(&[1101, 0, 0, 0, 99], &[3]).run(100),
+            Err(SymbolicError::SymbolicAddress { pc: 0 })
+        );
+        assert_eq!(
+            SymbolicMachine::new(&[109, i64::MAX, 109, 1, 99], &[]).run(100),
+            Err(SymbolicError::Overflow { pc: 2 })
+        );
+        assert_eq!(
+            SymbolicMachine::new(&[109, -1, 1201, i64::MIN, 0, 0, 99], &[]).run(100),
+            Err(SymbolicError::Overflow { pc: 2 })
+        );
+        assert_eq!(
+            SymbolicMachine::new(&[1105, 1, 0], &[]).run(100),
+            Err(SymbolicError::StepLimitExceeded { limit: 100 })
+        );
+        // Squares the variable forever
+        assert_eq!(
+            SymbolicMachine::new(&[2, 7, 7, 7, 1105, 1, 0, 0], &[7]).run(10_000),
+            Err(SymbolicError::TooComplex { pc: 0 })
+        );
+    }
+
+    #[test]
+    fn test_powers() {
+        let x = Polynomial::variable(0);
+        let mut power = x.clone();
+
+        for _ in 0..5 {
+            power = power.checked_mul(&power).unwrap();
+        }
+
+        assert_eq!(power.degree(), 32);
+        assert_eq!(power.term_count(), 1);
+        assert_eq!(power.format(&["x"]), "x^32");
+        assert_eq!(power.evaluate(&[2]), Some(1 << 32));
+        assert_eq!(power.evaluate(&[4]), None);
+
+        // x^2 * y + y^2 + x, in order of degree and then powers of x
+        let y = Polynomial::variable(1);
+        let polynomial = x
+            .checked_mul(&x)
+            .unwrap()
+            .checked_mul(&y)
+            .unwrap()
+            .checked_add(&y.checked_mul(&y).unwrap())
+            .unwrap()
+            .checked_add(&x)
+            .unwrap();
+        assert_eq!(polynomial.format(&["x", "y"]), "x^2*y + y^2 + x");
+        assert_eq!(
+            polynomial.solve(7, &[1..=2, -3..=3]),
+            vec![vec![1, -3], vec![1, 2], vec![2, 1]]
+        );
+    }
+}
@@ -0,0 +1,498 @@
+//! Running Intcode machines on threads of their own.
+//!
+//! Each machine in a `Pipeline` runs on its own thread, reading its input
+//! from one channel and sending its output down another, so a chain of
+//! programs runs however the operating system schedules it rather than
+//! taking turns like the amplifier chain. When a machine halts, its thread
+//! ends and its output channel closes, so the machine after it sees the end
+//! of its input instead of waiting forever.
+//!
+//! The threads keep a shared count of the machines waiting for input and the
+//! values on their way between machines. Once every machine still running is
+//! waiting and nothing is on its way, none of them can ever run again, so the
+//! pipeline stops with a deadlock, as the amplifier chain does. If a machine
+//! fails, the others are stopped too, even ones busy computing. A timeout on
+//! waiting for input can also be set, for when a machine that's merely slow
+//! should count as stuck.
+//!
+//! Channels deliver values in order, so however the threads are scheduled, a
+//! pipeline without a timeout produces the same result as the cooperative
+//! `AmplifierChain`.
+
+use super::{Machine, Status, VmError};
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a machine waiting for input checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Default, Debug)]
+struct Counts {
+    /// Machines whose threads haven't finished.
+    running: usize,
+    /// Running machines waiting for input.
+    waiting: usize,
+    /// Values sent that haven't been received yet.
+    in_flight: usize,
+    deadlocked: bool,
+}
+
+/// What the threads of a pipeline share.
+#[derive(Default, Debug)]
+struct Monitor {
+    counts: Mutex<Counts>,
+    /// Set once the pipeline has failed, to stop every machine.
+    stop: AtomicBool,
+}
+
+impl Monitor {
+    fn counts(&self) -> MutexGuard<'_, Counts> {
+        // Counts are only changed under the lock, so a thread that panicked
+        // while holding it can't have left them half updated
+        self.counts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// Stops the pipeline if no machine can make progress.
+    fn check_deadlock(&self, counts: &mut Counts) {
+        if counts.running > 0 && counts.waiting == counts.running && counts.in_flight == 0 {
+            counts.deadlocked = true;
+            self.stop();
+        }
+    }
+
+    /// Sends `value`, counting it as on its way. Returns false if the
+    /// receiver has gone.
+    ///
+    /// Values are sent and receivers dropped under the lock, so every value
+    /// counted either arrives or is discarded by `finish`.
+    fn send(&self, sender: &Sender<i64>, value: i64) -> bool {
+        let mut counts = self.counts();
+        let sent = sender.send(value).is_ok();
+
+        if sent {
+            counts.in_flight += 1;
+        }
+
+        sent
+    }
+
+    /// Passes on a value that has already been counted. Returns false if the
+    /// receiver has gone.
+    fn forward(&self, sender: &Sender<i64>, value: i64) -> bool {
+        let mut counts = self.counts();
+        let sent = sender.send(value).is_ok();
+
+        if !sent {
+            counts.in_flight -= 1;
+        }
+
+        sent
+    }
+
+    /// Records that a value has arrived somewhere it won't go any further.
+    fn consumed(&self) {
+        self.counts().in_flight -= 1;
+    }
+
+    /// Waits for the next value on `receiver`, which is `None` once every
+    /// sender has gone. Gives up with `Err(None)` if the pipeline is
+    /// stopped, or with `TimedOut` after `timeout`.
+    fn read(
+        &self,
+        index: usize,
+        receiver: &Receiver<i64>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<i64>, Option<PipelineError>> {
+        {
+            let mut counts = self.counts();
+            counts.waiting += 1;
+            self.check_deadlock(&mut counts);
+        }
+
+        let started = Instant::now();
+
+        let result = loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(value) => {
+                    let mut counts = self.counts();
+                    counts.in_flight -= 1;
+                    counts.waiting -= 1;
+                    return Ok(Some(value));
+                }
+                Err(RecvTimeoutError::Disconnected) => break Ok(None),
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.stopped() {
+                        break Err(None);
+                    }
+
+                    if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                        break Err(Some(PipelineError::TimedOut { index }));
+                    }
+                }
+            }
+        };
+
+        self.counts().waiting -= 1;
+        result
+    }
+
+    /// Records that a machine's thread is ending, discarding any input it
+    /// never read.
+    fn finish(&self, receiver: Receiver<i64>) {
+        let mut counts = self.counts();
+        counts.in_flight -= receiver.try_iter().count();
+        drop(receiver);
+        counts.running -= 1;
+        self.check_deadlock(&mut counts);
+    }
+}
+
+/// Runs the machine at `index` in a pipeline until it halts. Fails with
+/// `Err(None)` if it was stopped because of another machine.
+fn run_machine(
+    index: usize,
+    machine: &mut Machine,
+    input: &Receiver<i64>,
+    output: &Sender<i64>,
+    monitor: &Monitor,
+    timeout: Option<Duration>,
+) -> Result<(), Option<PipelineError>> {
+    let failed = |error| Some(PipelineError::Machine { index, error });
+
+    // Stepping rather than resuming lets a machine that never stops for
+    // input or output be stopped
+    while !monitor.stopped() {
+        match machine.step().map_err(failed)? {
+            None => {}
+            Some(Status::Halted) => return Ok(()),
+            Some(Status::NeedsInput) => match monitor.read(index, input, timeout)? {
+                Some(value) => machine.push_input(value),
+                None => {
+                    let pc = machine.program_counter();
+                    return Err(failed(VmError::InputExhausted {
+                        pc,
+                        instruction: machine.read(pc),
+                    }));
+                }
+            },
+            Some(Status::Output(value)) => {
+                machine.take_output();
+
+                if !monitor.send(output, value) {
+                    return Err(Some(PipelineError::Disconnected { index }));
+                }
+            }
+        }
+    }
+
+    Err(None)
+}
+
+/// A reason a pipeline stopped early.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PipelineError {
+    /// One of the machines failed.
+    Machine { index: usize, error: VmError },
+    /// Every machine that hasn't halted is waiting for input nobody will
+    /// send.
+    Deadlock,
+    /// A machine waited longer than the timeout for input.
+    TimedOut { index: usize },
+    /// A machine's output channel closed because the machine after it had
+    /// stopped.
+    Disconnected { index: usize },
+    /// A machine's thread panicked.
+    Panicked { index: usize },
+    /// The pipeline stopped without the last machine outputting anything.
+    NoOutput,
+}
+
+impl PipelineError {
+    /// Whether this is only the result of a neighbouring machine stopping:
+    /// the machines after a stopped one run out of input, and the ones
+    /// before it have nowhere to send their output.
+    fn is_knock_on(&self) -> bool {
+        matches!(
+            self,
+            PipelineError::Machine {
+                error: VmError::InputExhausted { .. },
+                ..
+            } | PipelineError::Disconnected { .. }
+        )
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::Machine { index, error } => write!(f, "machine {}: {}", index, error),
+            PipelineError::Deadlock => write!(f, "every machine is waiting for input"),
+            PipelineError::TimedOut { index } => {
+                write!(f, "machine {} timed out waiting for input", index)
+            }
+            PipelineError::Disconnected { index } => {
+                write!(f, "machine {} had nowhere to send its output", index)
+            }
+            PipelineError::Panicked { index } => write!(f, "machine {} panicked", index),
+            PipelineError::NoOutput => write!(f, "the pipeline produced no output"),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+/// A series of machines, each on its own thread, with every value a machine
+/// outputs becoming input for the next one.
+#[derive(Clone, Debug)]
+pub struct Pipeline {
+    machines: Vec<Machine>,
+    feedback: bool,
+    timeout: Option<Duration>,
+}
+
+impl Pipeline {
+    /// Connects `machines` in order. If `feedback` is set, the last
+    /// machine's output is routed back to the first as well.
+    pub fn new(machines: Vec<Machine>, feedback: bool) -> Pipeline {
+        Pipeline {
+            machines,
+            feedback,
+            timeout: None,
+        }
+    }
+
+    /// A pipeline of machines running copies of the same program, each given
+    /// its phase setting as its first input, like an `AmplifierChain`.
+    pub fn amplifiers(program: &[i64], phases: &[i64], feedback: bool) -> Pipeline {
+        let machines = phases
+            .iter()
+            .map(|&phase| {
+                let mut machine = Machine::new(program);
+                machine.push_input(phase);
+                machine
+            })
+            .collect();
+
+        Pipeline::new(machines, feedback)
+    }
+
+    /// Sets how long each machine waits for input before giving up. By
+    /// default they wait as long as another machine might still send
+    /// something.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Feeds `signal` to the first machine and waits for every machine to
+    /// stop, returning everything the last machine output.
+    pub fn run(self, signal: i64) -> Result<Vec<i64>, PipelineError> {
+        if self.machines.is_empty() {
+            return Err(PipelineError::NoOutput);
+        }
+
+        let monitor = Arc::new(Monitor::default());
+        monitor.counts().running = self.machines.len();
+
+        let (first_sender, mut receiver) = mpsc::channel();
+        monitor.send(&first_sender, signal);
+
+        let mut handles = Vec::with_capacity(self.machines.len());
+
+        for (index, mut machine) in self.machines.into_iter().enumerate() {
+            let (sender, next_receiver) = mpsc::channel();
+            let input = receiver;
+            let monitor = Arc::clone(&monitor);
+            let timeout = self.timeout;
+
+            handles.push(thread::spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    run_machine(index, &mut machine, &input, &sender, &monitor, timeout)
+                }))
+                .unwrap_or(Err(Some(PipelineError::Panicked { index })));
+
+                // Machines that have only lost a neighbour leave the rest
+                // running, as they would in an amplifier chain
+                if let Err(Some(error)) = &result {
+                    if !error.is_knock_on() {
+                        monitor.stop();
+                    }
+                }
+
+                monitor.finish(input);
+                result
+            }));
+
+            receiver = next_receiver;
+        }
+
+        // The last machine's output ends when its thread does. Until then,
+        // pass each value round to the first machine in a feedback loop.
+        let mut feedback = if self.feedback {
+            Some(first_sender)
+        } else {
+            drop(first_sender);
+            None
+        };
+        let mut outputs = Vec::new();
+
+        for value in receiver {
+            outputs.push(value);
+
+            match &feedback {
+                // The first machine may already have stopped
+                Some(sender) => {
+                    if !monitor.forward(sender, value) {
+                        feedback = None;
+                    }
+                }
+                None => monitor.consumed(),
+            }
+        }
+
+        drop(feedback);
+
+        let mut failures = Vec::new();
+
+        for (index, handle) in handles.into_iter().enumerate() {
+            match handle.join() {
+                Ok(Ok(())) | Ok(Err(None)) => {}
+                Ok(Err(Some(error))) => failures.push(error),
+                Err(_) => failures.push(PipelineError::Panicked { index }),
+            }
+        }
+
+        // Report the failure that caused the others
+        if let Some(&error) = failures.iter().find(|e| !e.is_knock_on()) {
+            return Err(error);
+        }
+
+        if monitor.counts().deadlocked {
+            return Err(PipelineError::Deadlock);
+        }
+
+        if let Some(&error) = failures.first() {
+            return Err(error);
+        }
+
+        if outputs.is_empty() {
+            Err(PipelineError::NoOutput)
+        } else {
+            Ok(outputs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::amplifiers::{permutations, AmplifierChain};
+    use crate::intcode::parse_program;
+
+    #[test]
+    fn test_matches_cooperative_scheduler() {
+        let series = parse_program("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+        let feedback = parse_program(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        );
+
+        for &(program, phases, loops) in &[
+            (&series, [0, 1, 2, 3, 4], false),
+            (&feedback, [5, 6, 7, 8, 9], true),
+        ] {
+            for permutation in permutations(&phases) {
+                let threaded = Pipeline::amplifiers(program, &permutation, loops).run(0);
+                let cooperative = AmplifierChain::new(program, &permutation, loops).run(0);
+
+                assert_eq!(
+                    threaded.map(|o| *o.last().unwrap()),
+                    Ok(cooperative.unwrap())
+                );
+            }
+        }
+
+        assert_eq!(
+            Pipeline::amplifiers(&feedback, &[9, 8, 7, 6, 5], true).run(0),
+            Ok(vec![129, 4257, 136_353, 4_363_425, 139_629_729])
+        );
+    }
+
+    #[test]
+    fn test_different_programs() {
+        // Doubles its input, then adds one to it
+        let machines = vec![
+            Machine::new(&[3, 0, 102, 2, 0, 0, 4, 0, 99]),
+            Machine::new(&[3, 0, 101, 1, 0, 0, 4, 0, 99]),
+        ];
+
+        assert_eq!(Pipeline::new(machines, false).run(20), Ok(vec![41]));
+    }
+
+    #[test]
+    fn test_errors() {
+        // Each machine waits for a third input that never comes
+        assert_eq!(
+            Pipeline::amplifiers(&[3, 0, 3, 0, 3, 0, 4, 0, 99], &[1, 2], true).run(0),
+            Err(PipelineError::Deadlock)
+        );
+
+        // The second machine waits for input after the first has halted
+        assert_eq!(
+            Pipeline::amplifiers(&[3, 0, 3, 0, 99], &[1, 2], false).run(0),
+            Err(PipelineError::Deadlock)
+        );
+
+        assert_eq!(
+            Pipeline::amplifiers(&[3, 0, 99], &[1], false).run(0),
+            Err(PipelineError::NoOutput)
+        );
+
+        // The machines either side of a failure stop too, but it's the
+        // failure that's reported
+        let machines = vec![
+            Machine::new(&[3, 0, 4, 0, 3, 0, 99]),
+            Machine::new(&[3, 0, 42]),
+            Machine::new(&[3, 0, 4, 0, 99]),
+        ];
+        assert_eq!(
+            Pipeline::new(machines, false).run(0),
+            Err(PipelineError::Machine {
+                index: 1,
+                error: VmError::UnknownOpcode {
+                    pc: 2,
+                    instruction: 42
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        // The first machine never talks, so the second gives up waiting and
+        // the first is stopped
+        let machines = vec![Machine::new(&[1105, 1, 0]), Machine::new(&[3, 0, 4, 0, 99])];
+        let mut pipeline = Pipeline::new(machines, false);
+        pipeline.set_timeout(Some(Duration::from_millis(50)));
+        assert_eq!(pipeline.run(0), Err(PipelineError::TimedOut { index: 1 }));
+
+        // A slow machine is fine without a timeout
+        let machines = vec![
+            Machine::new(&[3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99, 0]),
+            Machine::new(&[3, 0, 4, 0, 99]),
+        ];
+        assert_eq!(Pipeline::new(machines, false).run(200_000), Ok(vec![0]));
+    }
+}
@@ -0,0 +1,139 @@
+//! Instruction-level execution traces.
+//!
+//! A trace has one line per executed instruction:
+//!
+//! ```text
+//! 0 IN 225 [225]=1 <1
+//! 2 ADD 1100,-1,6 [6]=1099
+//! 6 OUT 0 >0
+//! ```
+//!
+//! Each line starts with the program counter, the mnemonic and the resolved
+//! operands (written-through operands show the address written). It then
+//! lists the memory cell written, the value read from input (`<`) and the
+//! value output (`>`), if any. Traces of two runs can be compared with any
+//! line-based diff tool to find where their control flow diverged.
+
+use super::{opcode_info, TraceEvent, Tracer, Word};
+use std::io::{self, Write};
+
+/// Formats a single event as a line of a trace, without the line ending.
+pub fn format_event<W: Word>(event: &TraceEvent<W>) -> String {
+    let mnemonic = opcode_info(event.opcode).map_or("???", |info| info.mnemonic);
+    let operands: Vec<String> = event.operands.iter().map(|o| o.to_string()).collect();
+    let mut line = format!("{} {}", event.pc, mnemonic);
+
+    if !operands.is_empty() {
+        line.push(' ');
+        line.push_str(&operands.join(","));
+    }
+
+    if let Some(write) = &event.write {
+        line.push_str(&format!(" [{}]={}", write.address, write.new_value));
+    }
+
+    if let Some(input) = &event.input {
+        line.push_str(&format!(" <{}", input));
+    }
+
+    if let Some(output) = &event.output {
+        line.push_str(&format!(" >{}", output));
+    }
+
+    line
+}
+
+/// A tracer that writes each event to `writer` as soon as it happens.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> TraceWriter<W> {
+        TraceWriter {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Flushes the trace, reporting the first error hit while writing it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write, V: Word> Tracer<V> for TraceWriter<W> {
+    fn trace(&mut self, event: &TraceEvent<V>) {
+        // Tracers can't fail, so remember the first error for `finish` and
+        // stop writing.
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", format_event(event)) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+/// A tracer that keeps every event in memory.
+#[derive(Clone, Default, Debug)]
+pub struct TraceRecorder<W = i64> {
+    pub events: Vec<TraceEvent<W>>,
+}
+
+impl<W: Clone> Tracer<W> for TraceRecorder<W> {
+    fn trace(&mut self, event: &TraceEvent<W>) {
+        self.events.push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Machine;
+
+    #[test]
+    fn test_trace_format() {
+        let mut machine = Machine::new(&[3, 9, 1008, 9, 8, 9, 4, 9, 99, 0]);
+        machine.push_input(8);
+
+        let mut writer = TraceWriter::new(Vec::new());
+        machine.run_traced(&mut writer).unwrap();
+        let trace = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        assert_eq!(
+            trace,
+            "0 IN 9 [9]=8 <8\n\
+             2 EQ 8,8,9 [9]=1\n\
+             6 OUT 1 >1\n\
+             8 HLT\n"
+        );
+    }
+
+    #[test]
+    fn test_traces_diverge_on_branch() {
+        // Day 5's "is the input zero?" sample, run with two different inputs
+        let program = [3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1];
+        let trace = |input| {
+            let mut machine = Machine::new(&program);
+            let mut recorder = TraceRecorder::default();
+            machine.push_input(input);
+            machine.run_traced(&mut recorder).unwrap();
+            recorder.events
+        };
+
+        let zero = trace(0);
+        let eight = trace(8);
+        let pcs = |events: &[TraceEvent]| events.iter().map(|e| e.pc).collect::<Vec<_>>();
+
+        assert_eq!(pcs(&zero), vec![0, 2, 5, 9, 11]);
+        assert_eq!(pcs(&eight), vec![0, 2, 9, 11]);
+        assert_eq!(zero[1].operands, vec![0, 9]);
+        assert_eq!(eight[1].operands, vec![8, 9]);
+    }
+}
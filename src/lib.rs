@@ -0,0 +1,3 @@
+//! The Intcode computer, shared by the puzzle solutions and the benchmarks.
+
+pub mod intcode;
@@ -1,5 +1,7 @@
 use std::env;
 use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
 
 mod day1;
 mod day2;
@@ -7,13 +9,474 @@ mod day3;
 mod day4;
 mod day5;
 mod day6;
+mod day7;
+
+use advent_of_code_2019::intcode;
 
 trait PuzzleSolver {
     fn description(&self) -> &'static str;
     fn solve(&self, input: &str);
 }
 
-fn main() {
+fn read_program(args: &[String]) -> Vec<i64> {
+    let program_file = args.get(2).expect("expected argument 2 to be present");
+    let source = fs::read_to_string(program_file).expect("could not read program file");
+    intcode::parse_program(&source)
+}
+
+/// Loads the machine named on the command line, which may either be a program
+/// or a snapshot of a paused machine.
+fn read_machine(args: &[String]) -> intcode::Machine {
+    let machine_file = args.get(2).expect("expected argument 2 to be present");
+    let source = fs::read_to_string(machine_file).expect("could not read program file");
+
+    if intcode::snapshot::is_snapshot(&source) {
+        intcode::snapshot::restore(&source).expect("could not restore snapshot")
+    } else {
+        intcode::Machine::new(&intcode::parse_program(&source))
+    }
+}
+
+/// Returns the value following `flag` in the command line, if it's present.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == flag).map(|index| {
+        args.get(index + 1)
+            .expect("expected a value after flag")
+            .as_str()
+    })
+}
+
+fn run_program(args: &[String]) {
+    if args.iter().any(|a| a == "--bigint") {
+        return run_big_program(args);
+    }
+
+    let mut machine = read_machine(args);
+    set_limits(&mut machine, args);
+
+    // Input values are the third argument, unless it's a flag
+    if let Some(inputs) = args.get(3).filter(|a| !a.starts_with("--")) {
+        for value in intcode::parse_program(inputs) {
+            machine.push_input(value);
+        }
+    }
+
+    let trace_writer = create_trace_writer(args);
+    let detector = if args.iter().any(|a| a == "--self-modification") {
+        Some(intcode::self_modification::SelfModificationDetector::new())
+    } else {
+        None
+    };
+
+    let mut tracers = (trace_writer, detector);
+    let result = machine.run_traced(&mut tracers);
+    let (trace_writer, detector) = tracers;
+
+    if let Some(trace_writer) = trace_writer {
+        trace_writer.finish().expect("could not write trace file");
+    }
+
+    let output: Vec<String> = machine.output().iter().map(|v| v.to_string()).collect();
+    println!("Output: {}", output.join(","));
+
+    if let Err(error) = result {
+        println!("Program failed: {}", error);
+    }
+
+    if let Some(detector) = detector {
+        print!("{}", detector.report());
+    }
+
+    if let Some(snapshot_file) = flag_value(args, "--save") {
+        fs::write(snapshot_file, intcode::snapshot::save(&machine))
+            .expect("could not write snapshot file");
+    }
+}
+
+/// Runs a program with its input read from a file, or standard input if no
+/// file is named, and each output value printed as soon as it's produced.
+fn pipe_program(args: &[String]) {
+    use intcode::devices::{self, TextInput, TextOutput};
+
+    let mut machine = read_machine(args);
+    let stdout = io::stdout();
+    let mut output = TextOutput(stdout.lock());
+
+    let result = match args.get(3) {
+        Some(input_file) => {
+            let file = fs::File::open(input_file).expect("could not open input file");
+            devices::run(
+                &mut machine,
+                &mut TextInput::new(io::BufReader::new(file)),
+                &mut output,
+            )
+        }
+        None => {
+            let stdin = io::stdin();
+            devices::run(&mut machine, &mut TextInput::new(stdin.lock()), &mut output)
+        }
+    };
+
+    if let Err(error) = result {
+        eprintln!("Program failed: {}", error);
+    }
+}
+
+/// Runs a chain of amplifiers on a thread each, then again under the
+/// cooperative scheduler to check that both give the same thruster signal.
+fn pipeline_program(args: &[String]) {
+    use intcode::amplifiers::AmplifierChain;
+    use intcode::threaded::Pipeline;
+
+    let program = read_program(args);
+    let phases = intcode::parse_program(args.get(3).expect("expected phase settings"));
+    let feedback = args.iter().any(|a| a == "--feedback");
+
+    let mut pipeline = Pipeline::amplifiers(&program, &phases, feedback);
+
+    if let Some(timeout) = flag_value(args, "--timeout") {
+        let millis = timeout.parse().expect("expected --timeout to be a number");
+        pipeline.set_timeout(Some(Duration::from_millis(millis)));
+    }
+
+    let threaded = pipeline.run(0);
+    let cooperative = AmplifierChain::new(&program, &phases, feedback).run(0);
+
+    match &threaded {
+        Ok(outputs) => {
+            let output: Vec<String> = outputs.iter().map(|v| v.to_string()).collect();
+            println!("Output: {}", output.join(","));
+        }
+        Err(error) => println!("Pipeline failed: {}", error),
+    }
+
+    match (threaded.ok().and_then(|o| o.last().copied()), cooperative) {
+        (Some(signal), Ok(expected)) if signal == expected => {
+            println!("The cooperative scheduler agrees")
+        }
+        (_, Ok(expected)) => println!("The cooperative scheduler got {}", expected),
+        (_, Err(error)) => println!("The cooperative scheduler failed: {}", error),
+    }
+}
+
+/// Runs a program under the profiler and prints where it spent its time.
+fn profile_program(args: &[String]) {
+    let program = read_program(args);
+    let mut machine = intcode::Machine::new(&program);
+    let mut profiler = intcode::profiler::Profiler::new();
+
+    if let Some(inputs) = args.get(3).filter(|a| !a.starts_with("--")) {
+        for value in intcode::parse_program(inputs) {
+            machine.push_input(value);
+        }
+    }
+
+    if let Err(error) = machine.run_traced(&mut profiler) {
+        println!("Program failed: {}", error);
+    }
+
+    let top = flag_value(args, "--top")
+        .map(|top| top.parse().expect("expected --top to be a number"))
+        .unwrap_or(10);
+    print!("{}", profiler.report(&program, top));
+}
+
+/// Applies the `--step-limit` and `--detect-loops` options.
+fn set_limits<W: intcode::Word>(machine: &mut intcode::Machine<W>, args: &[String]) {
+    if let Some(limit) = flag_value(args, "--step-limit") {
+        machine.set_step_limit(Some(
+            limit.parse().expect("expected --step-limit to be a number"),
+        ));
+    }
+
+    if args.iter().any(|a| a == "--detect-loops") {
+        machine.set_cycle_detection(true);
+    }
+}
+
+fn create_trace_writer(
+    args: &[String],
+) -> Option<intcode::trace::TraceWriter<io::BufWriter<fs::File>>> {
+    flag_value(args, "--trace").map(|trace_file| {
+        let file = fs::File::create(trace_file).expect("could not create trace file");
+        intcode::trace::TraceWriter::new(io::BufWriter::new(file))
+    })
+}
+
+/// Runs a program with arbitrary-precision integers.
+fn run_big_program(args: &[String]) {
+    let program_file = args.get(2).expect("expected argument 2 to be present");
+    let source = fs::read_to_string(program_file).expect("could not read program file");
+    let mut machine =
+        intcode::bigint::BigMachine::from_words(&intcode::bigint::parse_program(&source));
+    set_limits(&mut machine, args);
+
+    if let Some(inputs) = args.get(3).filter(|a| !a.starts_with("--")) {
+        for value in intcode::bigint::parse_program(inputs) {
+            machine.push_input(value);
+        }
+    }
+
+    let mut trace_writer = create_trace_writer(args);
+    let result = machine.run_traced(&mut trace_writer);
+
+    if let Some(trace_writer) = trace_writer {
+        trace_writer.finish().expect("could not write trace file");
+    }
+
+    let output: Vec<String> = machine.output().iter().map(|v| v.to_string()).collect();
+    println!("Output: {}", output.join(","));
+
+    if let Err(error) = result {
+        println!("Program failed: {}", error);
+    }
+}
+
+/// Prints a program's control-flow graph as DOT. With `--resolve`, the
+/// program is run on the given inputs first to find where its dynamic jumps
+/// go.
+fn print_cfg(args: &[String]) {
+    let program = read_program(args);
+
+    let trace = if args.iter().any(|a| a == "--resolve") {
+        let mut machine = intcode::Machine::new(&program);
+        let mut recorder = intcode::trace::TraceRecorder::default();
+
+        if let Some(inputs) = args.get(3).filter(|a| !a.starts_with("--")) {
+            for value in intcode::parse_program(inputs) {
+                machine.push_input(value);
+            }
+        }
+
+        // Whatever ran before a failure still tells us where jumps went
+        if let Err(error) = machine.run_traced(&mut recorder) {
+            eprintln!("Program failed: {}", error);
+        }
+
+        recorder.events
+    } else {
+        Vec::new()
+    };
+
+    let graph = intcode::cfg::ControlFlowGraph::build_with_trace(&program, &trace);
+    print!("{}", graph.to_dot());
+}
+
+/// Times `run` over `samples` batches of `runs` calls each, after one
+/// batch of warm-up, and returns the time per call in each batch.
+fn sample_times(samples: u32, runs: u32, mut run: impl FnMut()) -> Vec<Duration> {
+    for _ in 0..runs {
+        run();
+    }
+
+    (0..samples)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..runs {
+                run();
+            }
+            start.elapsed() / runs
+        })
+        .collect()
+}
+
+/// Prints the fastest, mean and slowest time per run, and the throughput
+/// they work out to for a program that executes `steps` instructions.
+fn report_times(name: &str, times: &[Duration], steps: u64) -> Duration {
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let fastest = times.iter().min().copied().unwrap_or_default();
+    let slowest = times.iter().max().copied().unwrap_or_default();
+    let throughput = |time: Duration| steps as f64 / time.as_secs_f64() / 1e6;
+
+    println!(
+        "{:<24} time: [{:?} {:?} {:?}]  throughput: [{:.1} {:.1} {:.1}] M instructions/s",
+        name,
+        fastest,
+        mean,
+        slowest,
+        throughput(slowest),
+        throughput(mean),
+        throughput(fastest)
+    );
+
+    mean
+}
+
+/// Measures a program's throughput under the interpreter, with and without
+/// its cache of decoded instructions, and as compiled code.
+fn bench_program(args: &[String]) {
+    let program = read_program(args);
+    let inputs = match args.get(3).filter(|a| !a.starts_with("--")) {
+        Some(inputs) => intcode::parse_program(inputs),
+        None => Vec::new(),
+    };
+    let parse_flag = |flag, default| {
+        flag_value(args, flag)
+            .map(|value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("expected {} to be a number", flag))
+            })
+            .unwrap_or(default)
+    };
+    let runs: u32 = parse_flag("--runs", 100);
+    let samples: u32 = parse_flag("--samples", 10).max(1);
+
+    let load = || {
+        let mut machine = intcode::Machine::new(&program);
+        for &value in &inputs {
+            machine.push_input(value);
+        }
+        machine
+    };
+
+    let mut interpreted = load();
+    if let Err(error) = interpreted.run() {
+        println!("Program failed: {}", error);
+    }
+    let steps = interpreted.steps();
+    println!(
+        "{} instructions per run, {} samples of {} runs",
+        steps, samples, runs
+    );
+
+    let times = sample_times(samples, runs, || {
+        let mut machine = load();
+        machine.set_decode_cache(false);
+        let _ = machine.run();
+    });
+    report_times("Interpreter (uncached)", &times, steps);
+
+    let times = sample_times(samples, runs, || {
+        let _ = load().run();
+    });
+    let interpreter_time = report_times("Interpreter", &times, steps);
+
+    let start = Instant::now();
+    let compiled = intcode::compiler::CompiledProgram::compile(&program, &[]);
+    let compile_time = start.elapsed();
+
+    let mut machine = load();
+    let _ = compiled.run(&mut machine);
+
+    if machine.output() != interpreted.output() || machine.memory() != interpreted.memory() {
+        println!("Compiled and interpreted runs disagree!");
+    }
+
+    let times = sample_times(samples, runs, || {
+        let _ = compiled.run(&mut load());
+    });
+    let compiled_time = report_times("Compiled", &times, steps);
+
+    println!(
+        "{} instructions compiled in {:?}, running at {:.1}x the speed of the interpreter",
+        compiled.compiled_count(),
+        compile_time,
+        interpreter_time.as_secs_f64() / compiled_time.as_secs_f64()
+    );
+}
+
+/// Fuzzes the interpreter, mutating the programs named on the command line
+/// or day 5's samples, and prints a minimal reproducer for each way programs
+/// failed. With `--save`, the reproducers are written out as fixtures.
+fn fuzz(args: &[String]) {
+    let mut seeds = Vec::new();
+    let mut rest = args.iter().skip(2);
+
+    while let Some(arg) = rest.next() {
+        if arg.starts_with("--") {
+            rest.next();
+        } else {
+            let source = fs::read_to_string(arg).expect("could not read program file");
+            seeds.push(intcode::parse_program(&source));
+        }
+    }
+
+    if seeds.is_empty() {
+        seeds = intcode::fuzz::DAY_5_SAMPLES
+            .iter()
+            .map(|sample| sample.to_vec())
+            .collect();
+    }
+
+    let parse_flag = |flag, default| {
+        flag_value(args, flag)
+            .map(|value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("expected {} to be a number", flag))
+            })
+            .unwrap_or(default)
+    };
+    let runs = parse_flag("--runs", 10_000);
+    let mut fuzzer = intcode::fuzz::Fuzzer::new(parse_flag("--seed", 0), &seeds);
+    let report = fuzzer.run(runs);
+
+    println!(
+        "Ran {} programs, which failed in {} ways",
+        report.runs,
+        report.crashes.len()
+    );
+
+    for (&class, crash) in &report.crashes {
+        let minimized: Vec<String> = crash.minimized.iter().map(|v| v.to_string()).collect();
+        println!(
+            "{}: {} programs, shrunk from {} to {} words: {}",
+            class,
+            crash.count,
+            crash.original.len(),
+            crash.minimized.len(),
+            minimized.join(",")
+        );
+
+        if let Some(directory) = flag_value(args, "--save") {
+            let fixture = fuzzer.fixture(class, crash);
+            let path = std::path::Path::new(directory).join(fixture.file_name());
+
+            fs::create_dir_all(directory).expect("could not create fixture directory");
+            fs::write(&path, fixture.to_text()).expect("could not write fixture");
+            println!("  saved {}", path.display());
+        }
+    }
+}
+
+/// Looks for day 2 style noun/verb pairs (the values at addresses 1 and 2)
+/// that leave each target value at address 0.
+fn search_program(args: &[String]) {
+    let program = read_program(args);
+    let targets = intcode::parse_program(args.get(3).expect("expected argument 3 to be present"));
+    let mut search = intcode::search::Search::new(&program, &[1, 2], 0..=99)
+        .expect("noun and verb searches are small");
+
+    if let Some(threads) = flag_value(args, "--threads") {
+        search.set_threads(threads.parse().expect("expected --threads to be a number"));
+    }
+
+    for target in targets {
+        let matches = |machine: &intcode::Machine| machine.read(0) == target;
+        let pairs = if args.iter().any(|a| a == "--all") {
+            search.all(matches)
+        } else {
+            search.first(matches).into_iter().collect()
+        };
+
+        if pairs.is_empty() {
+            println!("{}: no solution", target);
+        }
+
+        for pair in pairs {
+            println!(
+                "{}: noun {}, verb {} ({})",
+                target,
+                pair[0],
+                pair[1],
+                100 * pair[0] + pair[1]
+            );
+        }
+    }
+}
+
+fn solve_puzzle(args: &[String]) {
     let puzzle_solvers: Vec<Box<dyn PuzzleSolver>> = vec![
         Box::new(day1::DayOne),
         Box::new(day2::DayTwo),
@@ -21,14 +484,14 @@ fn main() {
         Box::new(day4::DayFour),
         Box::new(day5::DayFive),
         Box::new(day6::DaySix),
+        Box::new(day7::DaySeven),
     ];
 
-    let args: Vec<String> = env::args().collect();
     let puzzle_day = args
         .get(1)
         .expect("expected argument 1 to be present")
         .parse::<usize>()
-        .expect("expected argument 1 to be a number");
+        .expect("expected argument 1 to be a number or an Intcode tool");
     let input_file = args.get(2).expect("expected argument 2 to be present");
 
     let puzzle_input = fs::read_to_string(input_file).expect("could not read input file");
@@ -40,3 +503,52 @@ fn main() {
     println!("Solving {}", puzzle_solver.description());
     puzzle_solver.solve(&puzzle_input);
 }
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let command = args.get(1).expect("expected argument 1 to be present");
+
+    // Anything that isn't the name of an Intcode tool is a puzzle day
+    match command.as_str() {
+        "ascii" => {
+            let mut machine = read_machine(&args);
+            let stdin = io::stdin();
+
+            intcode::ascii::play(&mut machine, stdin.lock(), io::stdout())
+                .expect("could not talk to terminal");
+        }
+        "assemble" => {
+            let source_file = args.get(2).expect("expected argument 2 to be present");
+            let source = fs::read_to_string(source_file).expect("could not read source file");
+
+            match intcode::assembler::assemble(&source) {
+                Ok(program) => {
+                    let words: Vec<String> = program.iter().map(|w| w.to_string()).collect();
+                    println!("{}", words.join(","));
+                }
+                Err(error) => println!("Assembly failed: {}", error),
+            }
+        }
+        "bench" => bench_program(&args),
+        "cfg" => print_cfg(&args),
+        "debug" => {
+            let mut debugger = intcode::debugger::Debugger::new(read_machine(&args));
+            let stdin = io::stdin();
+
+            debugger
+                .repl(stdin.lock(), io::stdout())
+                .expect("could not talk to terminal");
+        }
+        "disassemble" => {
+            let program = read_program(&args);
+            print!("{}", intcode::disassembler::listing(&program));
+        }
+        "fuzz" => fuzz(&args),
+        "pipe" => pipe_program(&args),
+        "pipeline" => pipeline_program(&args),
+        "profile" => profile_program(&args),
+        "run" => run_program(&args),
+        "search" => search_program(&args),
+        _ => solve_puzzle(&args),
+    }
+}